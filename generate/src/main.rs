@@ -111,6 +111,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     for (name, fields) in structs {
+        // Functions with differing bodies are duplicated instead of having
+        // their statements `cfg`-guarded, so their bodies are compared whole.
+        let compared_whole: &[&str] = match name {
+            "ImplItemFn" | "ItemFn" => &["block"],
+            "TraitItemFn" => &["default"],
+            _ => &[],
+        };
         let mut has_attrs = false;
         let fields: Vec<_> = fields
             .iter()
@@ -120,7 +127,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     has_attrs = true;
                 }
             })
-            .map(|name| format_ident!("{name}"))
+            .map(|name| {
+                let field = format_ident!("{name}");
+                if compared_whole.contains(&name) {
+                    quote!(#[eq] #field)
+                } else {
+                    quote!(#field)
+                }
+            })
             .collect();
         let name = format_ident!("{name}");
         let add_attr = if has_attrs {
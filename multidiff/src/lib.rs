@@ -59,6 +59,7 @@ impl AppearsIn {
         Self(vec![idx])
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn add(self, idx: usize) -> Self {
         let mut vec = self.0;
         vec.push(idx);
@@ -72,6 +73,10 @@ impl AppearsIn {
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
 #[derive(Debug)]
@@ -218,7 +223,7 @@ pub fn multidiff<T: DiffableSequence>(
 
 pub fn multidiff_indexes<T: DiffableSequence>(to_diff: &[T]) -> Vec<Vec<Option<usize>>> {
     let mut current_indexes: Vec<usize> = to_diff.iter().map(|_| 0).collect();
-    multidiff(to_diff.into_iter())
+    multidiff(to_diff)
         .into_iter()
         .map(|(_, appears_in)| {
             current_indexes
@@ -0,0 +1,275 @@
+//! The `syn-merge` command-line tool.
+//!
+//! Merges Rust source files generated for different configurations into a
+//! single file, like `merge_files` followed by `prettyplease::unparse`.
+//! The merge can also be described by a `syn-merge.toml`, see
+//! `syn_merge::Config`.
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use syn_merge::{Config, ConfigError, ConfigInput, MergedOutput, Normalization, Style};
+
+const USAGE: &str = "\
+Usage: syn-merge [OPTIONS] --input <PATH>=<CFG>...
+       syn-merge [--check] [--verify] --config <PATH>
+
+Merge Rust source files generated for different configurations into one,
+guarding the parts that differ with `#[cfg]`s.
+
+Options:
+  -i, --input <PATH>=<CFG>  A source file, and the `cfg` that it is for,
+                            e.g. `linux.rs='target_os=\"linux\"'`
+      --print-cfg <PATH>    The output of `rustc --print cfg` for the
+                            preceding input
+  -o, --output <PATH>       Where to write the merged file [default: stdout]
+                            or directory, if the inputs are directories
+      --style <STYLE>       `pretty` to format with prettyplease, or
+                            `preserve` to keep the formatting of the first
+                            input [default: pretty]
+  -c, --config <PATH>       A `syn-merge.toml` describing the inputs, output
+                            and options, instead of the options above
+      --check               Instead of writing the output, fail if it
+                            differs from the output file
+      --verify              Fail if the merged file doesn't specialize back
+                            into each input
+  -h, --help                Print this help
+";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Args {
+    inputs: Vec<ConfigInput>,
+    output: Option<PathBuf>,
+    style: Style,
+    config: Option<PathBuf>,
+    check: bool,
+    verify: bool,
+}
+
+impl Args {
+    /// The config given by `--config`, or made from the other arguments.
+    fn config(&self) -> Result<Config, ConfigError> {
+        match &self.config {
+            Some(path) => Config::load(path),
+            None => Ok(Config {
+                inputs: self.inputs.clone(),
+                output: self.output.clone().unwrap_or_default(),
+                style: self.style,
+                minimize: false,
+                doc_cfg: false,
+                compile_error: None,
+                overrides: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// Parse the arguments, or return `None` if help was requested.
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut args = args.into_iter();
+    let mut parsed = Args {
+        inputs: Vec::new(),
+        output: None,
+        style: Style::Pretty,
+        config: None,
+        check: false,
+        verify: false,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("`{arg}` requires a value"))
+        };
+        match &*arg {
+            "-h" | "--help" => return Ok(None),
+            "-i" | "--input" => {
+                let value = value()?;
+                let Some((path, predicate)) = value.split_once('=') else {
+                    return Err(format!("expected `<PATH>=<CFG>`, found `{value}`"));
+                };
+                let predicate = syn::parse_str(predicate)
+                    .map_err(|err| format!("invalid cfg `{predicate}`: {err}"))?;
+                parsed.inputs.push(ConfigInput {
+                    path: path.into(),
+                    cfg: predicate,
+                    print_cfg: None,
+                });
+            }
+            "--print-cfg" => {
+                let path = value()?;
+                let Some(input) = parsed.inputs.last_mut() else {
+                    return Err("`--print-cfg` must follow an `--input`".to_string());
+                };
+                input.print_cfg = Some(path.into());
+            }
+            "-o" | "--output" => parsed.output = Some(value()?.into()),
+            "--style" => {
+                parsed.style = match &*value()? {
+                    "pretty" => Style::Pretty,
+                    "preserve" => Style::Preserve,
+                    style => return Err(format!("unknown style `{style}`")),
+                }
+            }
+            "-c" | "--config" => parsed.config = Some(value()?.into()),
+            "--check" => parsed.check = true,
+            "--verify" => parsed.verify = true,
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+    if parsed.config.is_some() {
+        if !parsed.inputs.is_empty() || parsed.output.is_some() {
+            return Err("`--config` can't be combined with `--input` or `--output`".to_string());
+        }
+    } else {
+        if parsed.inputs.is_empty() {
+            return Err("no inputs given".to_string());
+        }
+        if parsed.check && parsed.output.is_none() {
+            return Err("`--check` requires `--output`".to_string());
+        }
+        if parsed.output.is_none() && parsed.inputs.iter().any(|input| input.path.is_dir()) {
+            return Err("directory inputs require `--output`".to_string());
+        }
+    }
+    Ok(Some(parsed))
+}
+
+/// Check that `output` specializes back into each of its inputs.
+fn verify(output: &MergedOutput) -> Result<(), String> {
+    let merged = syn::parse_file(&output.contents).map_err(|err| {
+        format!(
+            "{}: merged file doesn't parse: {err}",
+            output.path.display()
+        )
+    })?;
+    let inputs = output
+        .sources
+        .iter()
+        .map(|(path, source, cfgs)| match syn::parse_file(source) {
+            Ok(file) => Ok((file, cfgs.clone())),
+            Err(err) => Err(format!("{}: {err}", path.display())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    syn_merge::verify(&inputs, &merged, &Normalization::new())
+        .map_err(|err| format!("{}: {err}", output.path.display()))
+}
+
+fn run(args: &Args) -> Result<ExitCode, ConfigError> {
+    let to_stdout = args.config.is_none() && args.output.is_none();
+    let outputs = args.config()?.run()?;
+
+    if args.verify {
+        for output in &outputs {
+            if let Err(err) = verify(output) {
+                eprintln!("error: {err}");
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    }
+
+    let mut stale = false;
+    for output in &outputs {
+        if to_stdout {
+            print!("{}", output.contents);
+        } else if args.check {
+            if fs::read_to_string(&output.path).ok().as_deref() != Some(&*output.contents) {
+                eprintln!("error: `{}` is out of date", output.path.display());
+                stale = true;
+            }
+        } else {
+            if let Some(parent) = output.path.parent() {
+                fs::create_dir_all(parent).map_err(|err| ConfigError::Io(parent.into(), err))?;
+            }
+            fs::write(&output.path, &output.contents)
+                .map_err(|err| ConfigError::Io(output.path.clone(), err))?;
+        }
+    }
+    Ok(if stale {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    run(&args).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        ExitCode::from(2)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use syn_merge::{Cfgs, Predicate};
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn args() {
+        let args = parse(&[
+            "--input",
+            "linux.rs=target_os=\"linux\"",
+            "--print-cfg",
+            "linux.cfg",
+            "-i",
+            "macos.rs=all(unix, target_os=\"macos\")",
+            "-o",
+            "merged.rs",
+            "--style",
+            "preserve",
+            "--check",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(args.inputs.len(), 2);
+        assert_eq!(args.inputs[0].path, Path::new("linux.rs"));
+        assert_eq!(
+            args.inputs[0].cfg,
+            Predicate::KeyValue("target_os".into(), "linux".into())
+        );
+        assert_eq!(
+            args.inputs[0].print_cfg.as_deref(),
+            Some(Path::new("linux.cfg"))
+        );
+        let cfgs = Cfgs::from_predicate(args.inputs[1].cfg.clone());
+        assert!(cfgs.holds(&Predicate::Name("unix".into())));
+        assert!(cfgs.holds(&Predicate::KeyValue("target_os".into(), "macos".into())));
+        assert_eq!(args.output.as_deref(), Some(Path::new("merged.rs")));
+        assert_eq!(args.style, Style::Preserve);
+        assert!(args.check && !args.verify);
+
+        assert_eq!(parse(&["--help"]), Ok(None));
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["--print-cfg", "a.cfg"]).is_err());
+        assert!(parse(&["-i", "a.rs=foo", "--check"]).is_err());
+        assert!(parse(&["-i", "a.rs"]).is_err());
+
+        let dir = env::temp_dir().display().to_string();
+        assert!(parse(&["-i", &format!("{dir}=foo")]).is_err());
+        assert!(parse(&["-i", &format!("{dir}=foo"), "-o", "out"]).is_ok());
+
+        let args = parse(&["--config", "syn-merge.toml", "--check"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(args.config.as_deref(), Some(Path::new("syn-merge.toml")));
+        assert!(parse(&["--config", "syn-merge.toml", "-i", "a.rs=foo"]).is_err());
+    }
+}
@@ -0,0 +1,432 @@
+//! `#[derive(Merge)]` for [`syn-merge`](https://docs.rs/syn-merge).
+//!
+//! Use it through the `derive` feature of `syn-merge`.
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Data, DataEnum, DeriveInput, Error, Fields, Ident, Index,
+    Member,
+};
+
+/// Derive `syn_merge::Merge` for a struct or an enum.
+///
+/// Structs are merged field by field, like `syn` structs are. Enums must be
+/// the same variant in all inputs to be merged, in which case the fields of
+/// that variant are merged.
+///
+/// The fields can be configured with the `#[merge(...)]` attribute:
+/// - `#[merge(attrs)]`: The `Vec<syn::Attribute>` of the node, that `#[cfg]`
+///   attributes are added to.
+/// - `#[merge(eq)]`: Compare with `PartialEq` instead of merging, the value
+///   of the first input is used.
+/// - `#[merge(skip)]`: Ignore when comparing, the value of the first input
+///   is used.
+///
+/// Enum variants without an `attrs` field, but with a single merged field,
+/// forward attributes to that field. Other variants, and structs without an
+/// `attrs` field, can't have a `#[cfg]` added, so merging fails if they only
+/// exist in some of the inputs.
+#[proc_macro_derive(Merge, attributes(merge))]
+pub fn derive_merge(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Merge,
+    Attrs,
+    Eq,
+    Skip,
+}
+
+struct Field {
+    member: Member,
+    kind: Kind,
+}
+
+impl Field {
+    fn binding(&self, prefix: &str) -> Ident {
+        match &self.member {
+            Member::Named(ident) => format_ident!("{}_{}", prefix, ident),
+            Member::Unnamed(index) => format_ident!("{}_{}", prefix, index.index),
+        }
+    }
+
+    fn is_merged(&self) -> bool {
+        matches!(self.kind, Kind::Merge | Kind::Attrs)
+    }
+}
+
+fn parse_fields(fields: &Fields) -> syn::Result<Vec<Field>> {
+    let mut res = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(i)),
+        };
+        let mut kind = Kind::Merge;
+        for attr in &field.attrs {
+            if !attr.path().is_ident("merge") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                let new = if meta.path.is_ident("attrs") {
+                    Kind::Attrs
+                } else if meta.path.is_ident("eq") {
+                    Kind::Eq
+                } else if meta.path.is_ident("skip") {
+                    Kind::Skip
+                } else {
+                    return Err(meta.error("expected `attrs`, `eq` or `skip`"));
+                };
+                if kind != Kind::Merge {
+                    return Err(meta.error("only one of `attrs`, `eq` or `skip` is allowed"));
+                }
+                kind = new;
+                Ok(())
+            })?;
+        }
+        res.push(Field { member, kind });
+    }
+    if res.iter().filter(|field| field.kind == Kind::Attrs).count() > 1 {
+        return Err(Error::new_spanned(
+            fields,
+            "only one field can be marked `#[merge(attrs)]`",
+        ));
+    }
+    Ok(res)
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let type_params: Vec<_> = input
+        .generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect();
+    let where_clause = input.generics.make_where_clause();
+    for param in type_params {
+        where_clause
+            .predicates
+            .push(parse_quote!(#param: ::syn_merge::Merge));
+    }
+
+    let body = match &input.data {
+        Data::Struct(data) => expand_struct(&input.ident, &parse_fields(&data.fields)?),
+        Data::Enum(data) if data.variants.is_empty() => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "`Merge` can't be derived for enums without variants",
+            ))
+        }
+        Data::Enum(data) => expand_enum(&input.ident, data)?,
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "`Merge` can't be derived for unions",
+            ))
+        }
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::syn_merge::Merge for #ident #ty_generics #where_clause {
+            #body
+        }
+    })
+}
+
+fn expand_struct(ty: &Ident, fields: &[Field]) -> TokenStream2 {
+    let eq = fields.iter().filter_map(|field| {
+        let member = &field.member;
+        match field.kind {
+            Kind::Merge | Kind::Attrs => Some(quote! {
+                && _cx.top_level_eq(&self.#member, &other.#member)
+            }),
+            Kind::Eq => Some(quote!(&& self.#member == other.#member)),
+            Kind::Skip => None,
+        }
+    });
+
+    let merged = fields.iter().map(|field| {
+        let member = &field.member;
+        if field.is_merged() {
+            quote! {
+                #member: _cx.merge(iter.clone().map(|(this, cfgs)| (&this.#member, cfgs)))?
+            }
+        } else {
+            quote!(#member: ::core::clone::Clone::clone(&first.#member))
+        }
+    });
+
+    let first = if fields.iter().all(Field::is_merged) {
+        None
+    } else {
+        Some(quote! {
+            let (first, _cfgs) = iter.clone().next().ok_or_else(|| {
+                ::syn_merge::Error::new(::syn_merge::ErrorKind::NoInput, "no input")
+            })?;
+        })
+    };
+
+    let attrs = fields.iter().find(|field| field.kind == Kind::Attrs);
+    let attr_methods = attrs.map(|field| {
+        let member = &field.member;
+        quote! {
+            fn attrs(&self) -> &[::syn_merge::__private::syn::Attribute] {
+                &self.#member
+            }
+
+            fn attrs_mut(
+                &mut self,
+            ) -> ::core::option::Option<&mut ::std::vec::Vec<::syn_merge::__private::syn::Attribute>> {
+                ::core::option::Option::Some(&mut self.#member)
+            }
+        }
+    });
+    let add_attr = match attrs {
+        Some(field) => {
+            let member = &field.member;
+            quote! {
+                self.#member.push(_attr);
+                ::core::result::Result::Ok(())
+            }
+        }
+        None => unsupported(&ty.to_string()),
+    };
+
+    quote! {
+        fn top_level_eq(&self, other: &Self, _cx: &::syn_merge::MergeContext<'_>) -> bool {
+            true #(#eq)*
+        }
+
+        fn merge<'a, I: ::core::iter::IntoIterator<Item = (&'a Self, &'a ::syn_merge::Cfgs)>>(
+            iter: I,
+            _cx: &::syn_merge::MergeContext<'_>,
+        ) -> ::core::result::Result<Self, ::syn_merge::Error>
+        where
+            Self: 'a,
+            I::IntoIter: ::core::clone::Clone,
+        {
+            let iter = ::core::iter::IntoIterator::into_iter(iter);
+            #first
+            ::core::result::Result::Ok(Self {
+                #(#merged,)*
+            })
+        }
+
+        fn add_attr(
+            &mut self,
+            _attr: ::syn_merge::__private::syn::Attribute,
+            _cx: &::syn_merge::MergeContext<'_>,
+        ) -> ::core::result::Result<(), ::syn_merge::Error> {
+            #add_attr
+        }
+
+        #attr_methods
+    }
+}
+
+fn expand_enum(ty: &Ident, data: &DataEnum) -> syn::Result<TokenStream2> {
+    let mut eq_arms = Vec::new();
+    let mut merge_arms = Vec::new();
+    let mut add_attr_arms = Vec::new();
+    let mut attrs_arms = Vec::new();
+    let mut attrs_mut_arms = Vec::new();
+
+    for variant in &data.variants {
+        let ident = &variant.ident;
+        let fields = parse_fields(&variant.fields)?;
+
+        // `top_level_eq`
+        let compared: Vec<_> = fields
+            .iter()
+            .filter(|field| field.kind != Kind::Skip)
+            .collect();
+        let members: Vec<_> = compared.iter().map(|field| &field.member).collect();
+        let this = compared.iter().map(|field| field.binding("this"));
+        let other = compared.iter().map(|field| field.binding("other"));
+        let eq = compared.iter().map(|field| {
+            let this = field.binding("this");
+            let other = field.binding("other");
+            if field.kind == Kind::Eq {
+                quote!(&& #this == #other)
+            } else {
+                quote!(&& _cx.top_level_eq(#this, #other))
+            }
+        });
+        eq_arms.push(quote! {
+            (
+                Self::#ident { #(#members: #this,)* .. },
+                Self::#ident { #(#members: #other,)* .. },
+            ) => true #(#eq)*
+        });
+
+        // `merge`
+        let merged: Vec<_> = fields.iter().filter(|field| field.is_merged()).collect();
+        let kept: Vec<_> = fields.iter().filter(|field| !field.is_merged()).collect();
+        let kept_members = kept.iter().map(|field| &field.member);
+        let kept_first: Vec<_> = kept.iter().map(|field| field.binding("first")).collect();
+        let merged_members: Vec<_> = merged.iter().map(|field| &field.member).collect();
+        let merged_this: Vec<_> = merged.iter().map(|field| field.binding("this")).collect();
+        let values = if merged.is_empty() {
+            None
+        } else {
+            Some(quote! {
+                // Collected to avoid infinitely nested iterator types
+                let values: ::std::vec::Vec<_> = iter
+                    .filter_map(|(this, cfgs)| match this {
+                        Self::#ident { #(#merged_members: #merged_this,)* .. } => {
+                            ::core::option::Option::Some(((#(#merged_this,)*), cfgs))
+                        }
+                        _ => ::core::option::Option::None,
+                    })
+                    .collect();
+            })
+        };
+        let merged_values = (0..merged.len()).map(|i| {
+            let i = Index::from(i);
+            quote! {
+                _cx.merge(values.iter().map(|(fields, cfgs)| (fields.#i, *cfgs)))?
+            }
+        });
+        let kept_pattern = kept_members.clone();
+        let kept_values = kept_first.iter();
+        merge_arms.push(quote! {
+            Self::#ident { #(#kept_pattern: #kept_first,)* .. } => {
+                #values
+                ::core::result::Result::Ok(Self::#ident {
+                    #(#merged_members: #merged_values,)*
+                    #(#kept_members: ::core::clone::Clone::clone(#kept_values),)*
+                })
+            }
+        });
+
+        // `add_attr`, `attrs` and `attrs_mut`
+        let target = match fields.iter().find(|field| field.kind == Kind::Attrs) {
+            Some(field) => Some((field, true)),
+            None if merged.len() == 1 => Some((merged[0], false)),
+            None => None,
+        };
+        match target {
+            Some((field, is_attrs)) => {
+                let member = &field.member;
+                let binding = field.binding("this");
+                let pattern = quote!(Self::#ident { #member: #binding, .. });
+                if is_attrs {
+                    add_attr_arms.push(quote! {
+                        #pattern => {
+                            #binding.push(_attr);
+                            ::core::result::Result::Ok(())
+                        }
+                    });
+                    attrs_arms.push(quote!(#pattern => &#binding[..],));
+                    attrs_mut_arms
+                        .push(quote!(#pattern => ::core::option::Option::Some(#binding),));
+                } else {
+                    add_attr_arms.push(quote!(#pattern => _cx.add_attr(#binding, _attr),));
+                    attrs_arms.push(quote!(#pattern => ::syn_merge::Merge::attrs(#binding),));
+                    attrs_mut_arms
+                        .push(quote!(#pattern => ::syn_merge::Merge::attrs_mut(#binding),));
+                }
+            }
+            None => {
+                let error = unsupported(&format!("{}::{}", ty, ident));
+                add_attr_arms.push(quote!(Self::#ident { .. } => #error,));
+                attrs_arms.push(quote!(Self::#ident { .. } => &[],));
+                attrs_mut_arms.push(quote!(Self::#ident { .. } => ::core::option::Option::None,));
+            }
+        }
+    }
+
+    Ok(quote! {
+        fn top_level_eq(&self, other: &Self, _cx: &::syn_merge::MergeContext<'_>) -> bool {
+            #[allow(unreachable_patterns)]
+            match (self, other) {
+                #(#eq_arms,)*
+                _ => false,
+            }
+        }
+
+        fn merge<'a, I: ::core::iter::IntoIterator<Item = (&'a Self, &'a ::syn_merge::Cfgs)>>(
+            iter: I,
+            _cx: &::syn_merge::MergeContext<'_>,
+        ) -> ::core::result::Result<Self, ::syn_merge::Error>
+        where
+            Self: 'a,
+            I::IntoIter: ::core::clone::Clone,
+        {
+            let iter = ::core::iter::IntoIterator::into_iter(iter);
+            // All values are the same variant, since they're `top_level_eq`
+            let (first, _cfgs) = iter.clone().next().ok_or_else(|| {
+                ::syn_merge::Error::new(::syn_merge::ErrorKind::NoInput, "no input")
+            })?;
+            match first {
+                #(#merge_arms)*
+            }
+        }
+
+        fn add_attr(
+            &mut self,
+            _attr: ::syn_merge::__private::syn::Attribute,
+            _cx: &::syn_merge::MergeContext<'_>,
+        ) -> ::core::result::Result<(), ::syn_merge::Error> {
+            match self {
+                #(#add_attr_arms)*
+            }
+        }
+
+        fn attrs(&self) -> &[::syn_merge::__private::syn::Attribute] {
+            match self {
+                #(#attrs_arms)*
+            }
+        }
+
+        fn attrs_mut(
+            &mut self,
+        ) -> ::core::option::Option<&mut ::std::vec::Vec<::syn_merge::__private::syn::Attribute>> {
+            match self {
+                #(#attrs_mut_arms)*
+            }
+        }
+    })
+}
+
+/// An `Unsupported` error for adding a `#[cfg]` to something without
+/// attributes.
+fn unsupported(name: &str) -> TokenStream2 {
+    let message = format!("can't add `#[cfg]` to `{}`", name);
+    quote! {
+        ::core::result::Result::Err(::syn_merge::Error::new(
+            ::syn_merge::ErrorKind::Unsupported,
+            #message,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_enum() {
+        let err = expand(parse_quote!(
+            enum Empty {}
+        ))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`Merge` can't be derived for enums without variants"
+        );
+        assert!(expand(parse_quote!(
+            enum One {
+                A,
+            }
+        ))
+        .is_ok());
+    }
+}
@@ -0,0 +1,270 @@
+//! Replace repeated complex predicates by short `cfg` aliases.
+use std::collections::{BTreeMap, BTreeSet};
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::visit::{self, Visit};
+use syn::visit_mut::{self, VisitMut};
+use syn::{parse_quote, Attribute, Expr, File};
+
+use crate::{CheckCfg, Predicate};
+
+/// Aliases for `cfg` predicates, e.g. `linux_like` for
+/// `any(all(target_os = "linux", target_arch = "x86_64"), target_os = "android")`.
+///
+/// The aliases are defined by a build script, see
+/// [`build_script`][Self::build_script].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CfgAliases {
+    aliases: BTreeMap<Predicate, String>,
+}
+
+impl CfgAliases {
+    /// No aliases.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an alias `name` for `predicate`.
+    pub fn alias(mut self, name: impl Into<String>, predicate: Predicate) -> Self {
+        self.aliases.insert(predicate, name.into());
+        self
+    }
+
+    /// Find aliases for the `all`, `any` and `not` predicates that are used
+    /// in at least `min_uses` `#[cfg]`s, `#[cfg_attr]`s or `cfg!`s in `file`.
+    ///
+    /// The names are derived from the names and values in the predicate,
+    /// e.g. `linux_x86_64_android` for the example above.
+    pub fn find(file: &File, min_uses: usize) -> Self {
+        let mut counter = Counter::default();
+        counter.visit_file(file);
+
+        let mut used_names: BTreeSet<String> = CheckCfg::from_file(file)
+            .names()
+            .map(String::from)
+            .collect();
+        let aliases = counter
+            .uses
+            .into_iter()
+            .filter(|(_, uses)| *uses >= min_uses)
+            .map(|(predicate, _)| {
+                let name = alias_name(&predicate, &mut used_names);
+                (predicate, name)
+            })
+            .collect();
+        Self { aliases }
+    }
+
+    /// The aliases and the predicates they stand for.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Predicate)> {
+        self.aliases
+            .iter()
+            .map(|(predicate, name)| (name.as_str(), predicate))
+    }
+
+    /// Replace the aliased predicates in the `#[cfg]`s, `#[cfg_attr]`s and
+    /// `cfg!`s in `file`.
+    ///
+    /// Only whole predicates are replaced, not parts of larger predicates.
+    pub fn apply(&self, mut file: File) -> File {
+        Replacer { aliases: self }.visit_file_mut(&mut file);
+        file
+    }
+
+    /// Statements for a `build.rs` that declare the aliases with
+    /// `cargo:rustc-check-cfg`, and enable each of them with
+    /// `cargo:rustc-cfg` if its predicate holds for the target.
+    ///
+    /// The predicates are evaluated using the `CARGO_CFG_*` environment
+    /// variables that Cargo passes to build scripts.
+    pub fn build_script(&self) -> TokenStream {
+        let aliases = self.iter().map(|(name, predicate)| {
+            let check_cfg = format!("cargo:rustc-check-cfg=cfg({name})");
+            let cfg = format!("cargo:rustc-cfg={name}");
+            let condition = build_script_condition(predicate);
+            quote! {
+                println!(#check_cfg);
+                if #condition {
+                    println!(#cfg);
+                }
+            }
+        });
+        quote! {
+            let cfg = |key: &str, value: ::core::option::Option<&str>| -> bool {
+                let var = ::std::format!("CARGO_CFG_{}", key.to_uppercase());
+                match (::std::env::var(var), value) {
+                    (::core::result::Result::Ok(_), ::core::option::Option::None) => true,
+                    (::core::result::Result::Ok(values), ::core::option::Option::Some(value)) => {
+                        values.split(',').any(|v| v == value)
+                    }
+                    (::core::result::Result::Err(_), _) => false,
+                }
+            };
+            #(#aliases)*
+        }
+    }
+}
+
+fn build_script_condition(predicate: &Predicate) -> TokenStream {
+    match predicate {
+        Predicate::Name(name) => quote!(cfg(#name, ::core::option::Option::None)),
+        Predicate::KeyValue(key, value) => {
+            quote!(cfg(#key, ::core::option::Option::Some(#value)))
+        }
+        Predicate::All(preds) if preds.is_empty() => quote!(true),
+        Predicate::Any(preds) if preds.is_empty() => quote!(false),
+        Predicate::All(preds) => {
+            let preds = preds.iter().map(build_script_operand);
+            quote!(#(#preds)&&*)
+        }
+        Predicate::Any(preds) => {
+            let preds = preds.iter().map(build_script_operand);
+            quote!(#(#preds)||*)
+        }
+        Predicate::Not(pred) => {
+            let pred = build_script_operand(pred);
+            quote!(!#pred)
+        }
+    }
+}
+
+/// Like [`build_script_condition`], but parenthesized if needed.
+fn build_script_operand(predicate: &Predicate) -> TokenStream {
+    let condition = build_script_condition(predicate);
+    match predicate {
+        Predicate::All(preds) | Predicate::Any(preds) if preds.len() > 1 => quote!((#condition)),
+        _ => condition,
+    }
+}
+
+/// Derive a name like `linux_x86_64_android` from the predicate.
+fn alias_name(predicate: &Predicate, used_names: &mut BTreeSet<String>) -> String {
+    let mut parts = Vec::new();
+    collect_name_parts(predicate, &mut parts);
+    let mut base: String = parts
+        .join("_")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if !base.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        base.insert_str(0, "cfg_");
+    }
+    let mut name = base.clone();
+    let mut n = 2;
+    while !used_names.insert(name.clone()) {
+        name = format!("{base}_{n}");
+        n += 1;
+    }
+    name
+}
+
+fn collect_name_parts(predicate: &Predicate, parts: &mut Vec<String>) {
+    match predicate {
+        Predicate::Name(name) => parts.push(name.clone()),
+        Predicate::KeyValue(_, value) => parts.push(value.clone()),
+        Predicate::All(preds) | Predicate::Any(preds) => {
+            for pred in preds {
+                collect_name_parts(pred, parts);
+            }
+        }
+        Predicate::Not(pred) => {
+            parts.push("not".to_string());
+            collect_name_parts(pred, parts);
+        }
+    }
+}
+
+/// Counts the uses of each complex predicate.
+#[derive(Default)]
+struct Counter {
+    uses: BTreeMap<Predicate, usize>,
+}
+
+impl Counter {
+    fn add(&mut self, predicate: Predicate) {
+        if !matches!(predicate, Predicate::Name(_) | Predicate::KeyValue(_, _)) {
+            *self.uses.entry(predicate).or_default() += 1;
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for Counter {
+    fn visit_attribute(&mut self, node: &'ast Attribute) {
+        if let Some(Ok(predicate)) = Predicate::from_attribute(node) {
+            self.add(predicate);
+        } else if let Some(Ok((predicate, metas))) = Predicate::from_cfg_attr(node) {
+            self.add(predicate);
+            for meta in metas {
+                self.visit_attribute(&Attribute {
+                    meta,
+                    ..node.clone()
+                });
+            }
+        }
+        visit::visit_attribute(self, node);
+    }
+
+    fn visit_macro(&mut self, node: &'ast syn::Macro) {
+        if node.path.is_ident("cfg") {
+            if let Ok(predicate) = node.parse_body::<Predicate>() {
+                self.add(predicate);
+            }
+        }
+        visit::visit_macro(self, node);
+    }
+}
+
+struct Replacer<'a> {
+    aliases: &'a CfgAliases,
+}
+
+impl Replacer<'_> {
+    fn alias(&self, predicate: &Predicate) -> Option<Predicate> {
+        let name = self.aliases.aliases.get(predicate)?;
+        Some(Predicate::Name(format_ident!("{}", name).to_string()))
+    }
+}
+
+impl VisitMut for Replacer<'_> {
+    fn visit_attribute_mut(&mut self, node: &mut Attribute) {
+        if let Some(Ok(predicate)) = Predicate::from_attribute(node) {
+            if let Some(alias) = self.alias(&predicate) {
+                let style = node.style;
+                *node = alias.attribute();
+                node.style = style;
+            }
+        } else if let Some(Ok((predicate, metas))) = Predicate::from_cfg_attr(node) {
+            let predicate = self.alias(&predicate).unwrap_or(predicate);
+            let metas = metas.into_iter().map(|meta| {
+                let mut attr = Attribute {
+                    meta,
+                    ..node.clone()
+                };
+                self.visit_attribute_mut(&mut attr);
+                attr.meta
+            });
+            node.meta = parse_quote!(cfg_attr(#predicate, #(#metas),*));
+        }
+        visit_mut::visit_attribute_mut(self, node);
+    }
+
+    fn visit_expr_mut(&mut self, node: &mut Expr) {
+        if let Expr::Macro(expr) = node {
+            if expr.mac.path.is_ident("cfg") {
+                if let Ok(predicate) = expr.mac.parse_body::<Predicate>() {
+                    if let Some(alias) = self.alias(&predicate) {
+                        expr.mac.tokens = quote!(#alias);
+                    }
+                }
+            }
+        }
+        visit_mut::visit_expr_mut(self, node);
+    }
+}
@@ -0,0 +1,150 @@
+//! Helpers for merging generated sources in `build.rs`.
+//!
+//! Requires the `pretty` feature.
+//!
+//! ```no_run
+//! use syn_merge::{build::Build, Cfgs, Predicate};
+//!
+//! let linux = Predicate::KeyValue("target_os".into(), "linux".into());
+//! let macos = Predicate::KeyValue("target_os".into(), "macos".into());
+//! Build::new()
+//!     .input("generated/linux.rs", Cfgs::from_predicate(linux))
+//!     .input("generated/macos.rs", Cfgs::from_predicate(macos))
+//!     .write("bindings.rs")
+//!     .unwrap();
+//! ```
+//!
+//! The crate then includes the merged file with
+//! `include!(concat!(env!("OUT_DIR"), "/bindings.rs"));`.
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    merge_sources_preserving_with_options, merge_sources_with_options, Cfgs, Diagnostic,
+    MergeOptions,
+};
+
+/// Merges generated sources into a file in `OUT_DIR`.
+#[derive(Debug, Clone, Default)]
+pub struct Build {
+    inputs: Vec<(PathBuf, Cfgs)>,
+    options: MergeOptions,
+    preserve_formatting: bool,
+    out_dir: Option<PathBuf>,
+}
+
+impl Build {
+    /// A build without inputs, writing to `OUT_DIR`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a source file, generated for the configuration `cfgs`.
+    pub fn input(mut self, path: impl Into<PathBuf>, cfgs: Cfgs) -> Self {
+        self.inputs.push((path.into(), cfgs));
+        self
+    }
+
+    /// The options to merge with.
+    pub fn options(mut self, options: MergeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Whether to keep the formatting and comments of the first input, see
+    /// [`merge_sources_preserving`][crate::merge_sources_preserving].
+    /// Otherwise the output is formatted with `prettyplease`.
+    pub fn preserve_formatting(mut self, value: bool) -> Self {
+        self.preserve_formatting = value;
+        self
+    }
+
+    /// The directory to write to, instead of the `OUT_DIR` environment
+    /// variable set by Cargo.
+    pub fn out_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.out_dir = Some(dir.into());
+        self
+    }
+
+    /// Merge the inputs, and write the result to `file_name` in the output
+    /// directory. Returns the path of the written file.
+    ///
+    /// Prints `cargo:rerun-if-changed` for each input, and doesn't touch
+    /// the file if its contents are unchanged.
+    pub fn write(&self, file_name: impl AsRef<Path>) -> Result<PathBuf, BuildError> {
+        for (path, _) in &self.inputs {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+        let out_dir = match &self.out_dir {
+            Some(dir) => dir.clone(),
+            None => env::var_os("OUT_DIR").ok_or(BuildError::NoOutDir)?.into(),
+        };
+
+        let sources = self
+            .inputs
+            .iter()
+            .map(|(path, cfgs)| {
+                let source =
+                    fs::read_to_string(path).map_err(|err| BuildError::Io(path.clone(), err))?;
+                Ok((path, source, cfgs.clone()))
+            })
+            .collect::<Result<Vec<_>, BuildError>>()?;
+        let input: Vec<_> = sources
+            .iter()
+            .map(|(path, source, cfgs)| (path, &**source, cfgs.clone()))
+            .collect();
+        let contents = if self.preserve_formatting {
+            merge_sources_preserving_with_options(&input, &self.options)
+        } else {
+            merge_sources_with_options(&input, &self.options)
+                .map(|file| prettyplease::unparse(&file))
+        }
+        .map_err(BuildError::Merge)?;
+
+        let path = out_dir.join(file_name);
+        write_if_changed(&path, &contents).map_err(|err| BuildError::Io(path.clone(), err))?;
+        Ok(path)
+    }
+}
+
+/// Write `contents` to `path`, unless it already has those contents.
+///
+/// Returns whether the file was written. Leaving the file alone keeps its
+/// modification time, so that Cargo doesn't rebuild needlessly.
+pub fn write_if_changed(path: &Path, contents: &str) -> io::Result<bool> {
+    if fs::read_to_string(path).ok().as_deref() == Some(contents) {
+        return Ok(false);
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    Ok(true)
+}
+
+/// An error from [`Build::write`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// `OUT_DIR` isn't set, i.e. this isn't running in a build script.
+    NoOutDir,
+    /// A file couldn't be read or written.
+    Io(PathBuf, io::Error),
+    /// Merging failed.
+    Merge(Diagnostic),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoOutDir => write!(f, "error: `OUT_DIR` is not set"),
+            Self::Io(path, err) => write!(f, "error: `{}`: {err}", path.display()),
+            Self::Merge(diagnostic) => write!(f, "{diagnostic}"),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
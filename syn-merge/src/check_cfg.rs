@@ -0,0 +1,158 @@
+//! Collect the `cfg` names and values used in a file, for `check-cfg`.
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+use syn::visit::{self, Visit};
+use syn::{Attribute, File, Macro};
+
+use crate::Predicate;
+
+/// Names that rustc or Cargo already know about, and which therefore don't
+/// need to be declared.
+const WELL_KNOWN_NAMES: &[&str] = &[
+    "clippy",
+    "debug_assertions",
+    "doc",
+    "doctest",
+    "feature",
+    "fmt_debug",
+    "miri",
+    "overflow_checks",
+    "panic",
+    "proc_macro",
+    "relocation_model",
+    "rustfmt",
+    "sanitize",
+    "sanitizer_cfi_generalize_pointers",
+    "sanitizer_cfi_normalize_integers",
+    "target_abi",
+    "target_arch",
+    "target_endian",
+    "target_env",
+    "target_family",
+    "target_feature",
+    "target_has_atomic",
+    "target_has_atomic_equal_alignment",
+    "target_has_atomic_load_store",
+    "target_os",
+    "target_pointer_width",
+    "target_thread_local",
+    "target_vendor",
+    "test",
+    "ub_checks",
+    "unix",
+    "windows",
+];
+
+/// The `cfg` names and values used in a file.
+///
+/// Collected from `#[cfg]`, `#[cfg_attr]` and `cfg!`. Predicates that fail
+/// to parse are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckCfg {
+    /// Maps each name to its values, where `None` is the bare name.
+    cfgs: BTreeMap<String, BTreeSet<Option<String>>>,
+}
+
+impl CheckCfg {
+    /// Collect the names and values used in `file`.
+    pub fn from_file(file: &File) -> Self {
+        let mut this = Self::default();
+        this.visit_file(file);
+        this
+    }
+
+    /// The names that are used, including well-known ones.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.cfgs.keys().map(String::as_str)
+    }
+
+    /// The values that `name` is used with, where `None` is the bare name.
+    pub fn values(&self, name: &str) -> impl Iterator<Item = Option<&str>> {
+        self.cfgs
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(Option::as_deref)
+    }
+
+    /// The `cargo::rustc-check-cfg=...` lines declaring the names that are
+    /// not well-known.
+    pub fn build_script_lines(&self) -> Vec<String> {
+        self.cfgs
+            .iter()
+            .filter(|(name, _)| !WELL_KNOWN_NAMES.contains(&&***name))
+            .map(|(name, values)| {
+                if values.iter().all(Option::is_none) {
+                    return format!("cargo::rustc-check-cfg=cfg({name})");
+                }
+                let values: Vec<_> = values
+                    .iter()
+                    .map(|value| match value {
+                        Some(value) => format!("{value:?}"),
+                        None => "none()".to_string(),
+                    })
+                    .collect();
+                format!(
+                    "cargo::rustc-check-cfg=cfg({name}, values({}))",
+                    values.join(", ")
+                )
+            })
+            .collect()
+    }
+
+    /// Write [`build_script_lines`][Self::build_script_lines] to `out`, one
+    /// per line, e.g. to stdout in a `build.rs`.
+    pub fn write_build_script_lines(&self, mut out: impl io::Write) -> io::Result<()> {
+        for line in self.build_script_lines() {
+            writeln!(out, "{line}")?;
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, predicate: &Predicate) {
+        match predicate {
+            Predicate::Name(name) => {
+                self.cfgs.entry(name.clone()).or_default().insert(None);
+            }
+            Predicate::KeyValue(key, value) => {
+                self.cfgs
+                    .entry(key.clone())
+                    .or_default()
+                    .insert(Some(value.clone()));
+            }
+            Predicate::All(preds) | Predicate::Any(preds) => {
+                for pred in preds {
+                    self.add(pred);
+                }
+            }
+            Predicate::Not(pred) => self.add(pred),
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for CheckCfg {
+    fn visit_attribute(&mut self, node: &'ast Attribute) {
+        if let Some(Ok(predicate)) = Predicate::from_attribute(node) {
+            self.add(&predicate);
+        } else if let Some(Ok((predicate, metas))) = Predicate::from_cfg_attr(node) {
+            self.add(&predicate);
+            for meta in metas {
+                self.visit_attribute(&Attribute {
+                    meta,
+                    ..node.clone()
+                });
+            }
+        }
+        visit::visit_attribute(self, node);
+    }
+
+    fn visit_macro(&mut self, node: &'ast Macro) {
+        if node.path.is_ident("cfg") {
+            if let Ok(predicate) = node.parse_body::<Predicate>() {
+                self.add(&predicate);
+            }
+        }
+        visit::visit_macro(self, node);
+    }
+}
@@ -0,0 +1,335 @@
+//! Merges described by a `syn-merge.toml` file.
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    merge_sources_preserving_with_options, merge_sources_with_options, Cfgs, Diagnostic,
+    MergeOptions, Predicate,
+};
+
+/// A repeatable merge, usually loaded from a `syn-merge.toml`:
+///
+/// ```toml
+/// output = "src/bindings.rs"
+/// style = "preserve"
+/// minimize = true
+///
+/// [[input]]
+/// path = "generated/linux.rs"
+/// cfg = 'target_os = "linux"'
+/// print-cfg = "generated/linux.cfg"
+///
+/// [[input]]
+/// path = "generated/macos.rs"
+/// cfg = 'target_os = "macos"'
+///
+/// [[override]]
+/// module = "sys::tests"
+/// merge = false
+/// ```
+///
+/// If the inputs are directories, each `.rs` file in them is merged into
+/// the file with the same relative path in the output directory.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// The files or directories to merge, in order.
+    #[serde(rename = "input")]
+    pub inputs: Vec<ConfigInput>,
+    /// Where to write the merged file or directory.
+    pub output: PathBuf,
+    /// How to format the output.
+    #[serde(default)]
+    pub style: Style,
+    /// See [`MergeOptions::minimize`].
+    #[serde(default)]
+    pub minimize: bool,
+    /// See [`MergeOptions::doc_cfg`].
+    #[serde(default)]
+    pub doc_cfg: bool,
+    /// See [`MergeOptions::compile_error`].
+    #[serde(default)]
+    pub compile_error: Option<String>,
+    /// Changes to how some parts are merged.
+    #[serde(default, rename = "override")]
+    pub overrides: Vec<Override>,
+}
+
+/// An input of a [`Config`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ConfigInput {
+    /// The file or directory.
+    pub path: PathBuf,
+    /// The configuration that the input was generated for.
+    #[serde(deserialize_with = "predicate")]
+    pub cfg: Predicate,
+    /// The output of `rustc --print cfg` for the configuration, see
+    /// [`Cfgs::from_print_cfg`]. Otherwise the facts are taken from `cfg`,
+    /// see [`Cfgs::from_predicate`].
+    #[serde(default)]
+    pub print_cfg: Option<PathBuf>,
+}
+
+/// A change to how some inline modules are merged.
+///
+/// When several overrides match a module, the last one applies, so that
+/// e.g. `module = "*"` with `merge = false` followed by `module = "ffi"` with
+/// `merge = true` only merges inside the `ffi` modules.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Override {
+    /// The path of the modules that this applies to within the merged file,
+    /// like `sys::tests`. A path without `::` is a name, which matches
+    /// modules with that name at any depth.
+    ///
+    /// `*` matches any sequence of characters, e.g. `test*` matches both
+    /// `tests` and `test_utils`, and `sys::*` matches all modules in `sys`.
+    pub module: String,
+    /// Whether to merge inside the modules. If not, modules that differ
+    /// between the inputs are emitted once per version.
+    #[serde(default = "yes")]
+    pub merge: bool,
+}
+
+impl Override {
+    /// Whether this applies to the module at `path`, like `sys::tests`.
+    pub fn matches(&self, path: &str) -> bool {
+        if self.module.contains("::") {
+            glob_matches(&self.module, path)
+        } else {
+            let name = path.rsplit_once("::").map_or(path, |(_, name)| name);
+            glob_matches(&self.module, name)
+        }
+    }
+}
+
+/// Match `name` against `pattern`, where `*` matches any sequence of
+/// characters.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            name.char_indices()
+                .map(|(i, _)| i)
+                .chain(Some(name.len()))
+                .any(|i| glob_matches(rest, &name[i..]))
+        }
+    }
+}
+
+fn yes() -> bool {
+    true
+}
+
+/// How merged files are formatted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Style {
+    /// Formatted with `prettyplease`, the default.
+    #[default]
+    Pretty,
+    /// With the formatting and comments of the first input, see
+    /// [`merge_sources_preserving`][crate::merge_sources_preserving].
+    Preserve,
+}
+
+fn predicate<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Predicate, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    syn::parse_str(&s).map_err(serde::de::Error::custom)
+}
+
+/// An error from loading or running a [`Config`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// A file couldn't be read.
+    Io(PathBuf, io::Error),
+    /// The config file is invalid.
+    Parse(toml::de::Error),
+    /// A `print-cfg` file is invalid.
+    PrintCfg(PathBuf, syn::Error),
+    /// The inputs are a mix of files and directories.
+    MixedInputs,
+    /// Merging failed.
+    Merge(Diagnostic),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, err) => write!(f, "error: can't read `{}`: {err}", path.display()),
+            Self::Parse(err) => write!(f, "error: invalid config: {err}"),
+            Self::PrintCfg(path, err) => write!(f, "error: {}: {err}", path.display()),
+            Self::MixedInputs => write!(f, "error: inputs must be all files or all directories"),
+            Self::Merge(diagnostic) => write!(f, "{diagnostic}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// A merged file, see [`Config::run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergedOutput {
+    /// Where the file should be written.
+    pub path: PathBuf,
+    /// The merged source.
+    pub contents: String,
+    /// The path, source and configuration of each input. The source is
+    /// empty if an input directory doesn't contain the file.
+    pub sources: Vec<(PathBuf, String, Cfgs)>,
+}
+
+fn read(path: &Path) -> Result<String, ConfigError> {
+    fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))
+}
+
+impl Config {
+    /// Parse a config, with paths relative to the current directory.
+    pub fn from_toml(s: &str) -> Result<Self, ConfigError> {
+        toml::from_str(s).map_err(ConfigError::Parse)
+    }
+
+    /// Load a config file, with paths relative to the directory it's in.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let mut config = Self::from_toml(&read(path)?)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for input in &mut config.inputs {
+            input.path = dir.join(&input.path);
+            if let Some(print_cfg) = &mut input.print_cfg {
+                *print_cfg = dir.join(&*print_cfg);
+            }
+        }
+        config.output = dir.join(&config.output);
+        Ok(config)
+    }
+
+    /// The options to merge with.
+    pub fn merge_options(&self) -> MergeOptions {
+        let mut options = MergeOptions::new()
+            .minimize(self.minimize)
+            .doc_cfg(self.doc_cfg);
+        if let Some(message) = &self.compile_error {
+            options = options.compile_error(message);
+        }
+        if self.overrides.iter().any(|o| !o.merge) {
+            let overrides = self.overrides.clone();
+            options = options.module_top_level_eq_with(move |path, a, b| {
+                if merges_module(&overrides, path) {
+                    None
+                } else {
+                    Some(a == b)
+                }
+            });
+        }
+        options
+    }
+
+    /// Merge the inputs, and return the merged files without writing them.
+    pub fn run(&self) -> Result<Vec<MergedOutput>, ConfigError> {
+        let cfgs = self
+            .inputs
+            .iter()
+            .map(|input| match &input.print_cfg {
+                Some(path) => Cfgs::from_print_cfg(input.cfg.clone(), &read(path)?)
+                    .map_err(|err| ConfigError::PrintCfg(path.clone(), err)),
+                None => Ok(Cfgs::from_predicate(input.cfg.clone())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let dirs = self
+            .inputs
+            .iter()
+            .filter(|input| input.path.is_dir())
+            .count();
+        let files: Vec<(PathBuf, PathBuf)> = if dirs == 0 {
+            vec![(PathBuf::new(), self.output.clone())]
+        } else if dirs == self.inputs.len() {
+            let mut files = BTreeSet::new();
+            for input in &self.inputs {
+                rust_files(&input.path, Path::new(""), &mut files)?;
+            }
+            files
+                .into_iter()
+                .map(|file| (file.clone(), self.output.join(file)))
+                .collect()
+        } else {
+            return Err(ConfigError::MixedInputs);
+        };
+
+        let options = self.merge_options();
+        files
+            .into_iter()
+            .map(|(relative, output)| {
+                let sources = self
+                    .inputs
+                    .iter()
+                    .zip(&cfgs)
+                    .map(|(input, cfgs)| {
+                        let path = if dirs == 0 {
+                            input.path.clone()
+                        } else {
+                            input.path.join(&relative)
+                        };
+                        let source = if dirs != 0 && !path.exists() {
+                            String::new()
+                        } else {
+                            read(&path)?
+                        };
+                        Ok((path, source, cfgs.clone()))
+                    })
+                    .collect::<Result<Vec<_>, ConfigError>>()?;
+                let input: Vec<_> = sources
+                    .iter()
+                    .map(|(path, source, cfgs)| (path, &**source, cfgs.clone()))
+                    .collect();
+                let contents = match self.style {
+                    Style::Pretty => merge_sources_with_options(&input, &options)
+                        .map(|file| prettyplease::unparse(&file)),
+                    Style::Preserve => merge_sources_preserving_with_options(&input, &options),
+                }
+                .map_err(ConfigError::Merge)?;
+                Ok(MergedOutput {
+                    path: output,
+                    contents,
+                    sources,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Whether to merge inside the module at `path`, according to the last
+/// override that matches it.
+fn merges_module(overrides: &[Override], path: &str) -> bool {
+    overrides
+        .iter()
+        .rev()
+        .find(|o| o.matches(path))
+        .map_or(true, |o| o.merge)
+}
+
+/// Collect the paths of the `.rs` files in `dir`, relative to its root.
+fn rust_files(root: &Path, dir: &Path, files: &mut BTreeSet<PathBuf>) -> Result<(), ConfigError> {
+    let path = root.join(dir);
+    let entries = fs::read_dir(&path).map_err(|err| ConfigError::Io(path.clone(), err))?;
+    for entry in entries {
+        let entry = entry.map_err(|err| ConfigError::Io(path.clone(), err))?;
+        let relative = dir.join(entry.file_name());
+        if entry.path().is_dir() {
+            rust_files(root, &relative, files)?;
+        } else if relative.extension().is_some_and(|ext| ext == "rs") {
+            files.insert(relative);
+        }
+    }
+    Ok(())
+}
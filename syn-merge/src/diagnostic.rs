@@ -0,0 +1,135 @@
+//! Errors that point into the original source files.
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use proc_macro2::Span;
+
+use crate::{Cfgs, Error, ErrorKind};
+
+/// A position in a source file, along with the text of that line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// The line, starting at 1.
+    pub line: usize,
+    /// The column in characters, starting at 1.
+    pub column: usize,
+    /// The number of characters to underline, at least 1.
+    pub len: usize,
+    /// The text of the line.
+    pub text: String,
+}
+
+impl Location {
+    /// The location of `span` in `source`, if the span points into a parsed
+    /// source.
+    #[cfg(feature = "diagnostics")]
+    fn new(span: Span, source: &str) -> Option<Self> {
+        // Spans that don't come from parsed source, like `Span::call_site()`,
+        // don't have any text.
+        span.source_text()?;
+        let start = span.start();
+        let end = span.end();
+        let text = source.lines().nth(start.line.checked_sub(1)?)?.to_string();
+        let len = if end.line == start.line {
+            end.column.saturating_sub(start.column)
+        } else {
+            text.chars().count().saturating_sub(start.column)
+        };
+        Some(Self {
+            line: start.line,
+            column: start.column + 1,
+            len: len.max(1),
+            text,
+        })
+    }
+
+    /// Spans only know their line and column with the `diagnostics` feature.
+    #[cfg(not(feature = "diagnostics"))]
+    fn new(_span: Span, _source: &str) -> Option<Self> {
+        None
+    }
+}
+
+/// An error from [`merge_sources`][crate::merge_sources], with the file and
+/// position that it occurred at where known.
+///
+/// Renders like a `rustc` diagnostic:
+///
+/// ```text
+/// error: can't add `#[cfg]` to verbatim tokens
+///  --> src/b.rs:2:1
+///   |
+/// 2 | fn b();
+///   | ^^^^^^^
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The kind of error.
+    pub kind: ErrorKind,
+    /// The error message, without location.
+    pub message: String,
+    /// The index of the input that caused the error, if known.
+    pub input: Option<usize>,
+    /// The path of that input.
+    pub path: Option<PathBuf>,
+    /// The position in that input. Requires the `diagnostics` feature.
+    pub location: Option<Location>,
+}
+
+impl Diagnostic {
+    /// A diagnostic for a failure to parse the source of input `idx`.
+    pub(crate) fn parse(idx: usize, path: &Path, source: &str, err: &syn::Error) -> Self {
+        Self {
+            kind: ErrorKind::Parse,
+            message: err.to_string(),
+            input: Some(idx),
+            path: Some(path.to_path_buf()),
+            location: Location::new(err.span(), source),
+        }
+    }
+
+    /// A diagnostic for a merge error, given the paths and sources of the
+    /// inputs.
+    pub(crate) fn merge<P: AsRef<Path>>(err: &Error, input: &[(P, &str, Cfgs)]) -> Self {
+        let source = err.input().and_then(|idx| input.get(idx));
+        Self {
+            kind: err.kind(),
+            message: err.message().to_string(),
+            input: err.input(),
+            path: source.map(|(path, _, _)| path.as_ref().to_path_buf()),
+            location: source
+                .zip(err.span())
+                .and_then(|((_, source, _), span)| Location::new(span, source)),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error: {}", self.message)?;
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let Some(location) = &self.location else {
+            return write!(f, "\n --> {}", path.display());
+        };
+        let gutter = " ".repeat(location.line.to_string().len());
+        write!(
+            f,
+            "\n{gutter}--> {}:{}:{}",
+            path.display(),
+            location.line,
+            location.column,
+        )?;
+        write!(f, "\n{gutter} |")?;
+        write!(f, "\n{} | {}", location.line, location.text)?;
+        write!(
+            f,
+            "\n{gutter} | {}{}",
+            " ".repeat(location.column - 1),
+            "^".repeat(location.len),
+        )
+    }
+}
+
+impl std::error::Error for Diagnostic {}
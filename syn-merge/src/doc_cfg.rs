@@ -0,0 +1,71 @@
+//! Show the `cfg`s of public items in the documentation.
+use syn::visit_mut::{self, VisitMut};
+use syn::{parse_quote, Attribute, File, ForeignItem, ImplItem, Item, Visibility};
+
+use crate::Predicate;
+
+/// Add `#[cfg_attr(docsrs, doc(cfg(pred)))]` after the `#[cfg(pred)]` of
+/// every public item.
+pub(crate) fn add_doc_cfgs(file: &mut File) {
+    DocCfgs.visit_file_mut(file);
+}
+
+struct DocCfgs;
+
+fn add_doc_cfg(vis: &Visibility, attrs: &mut Vec<Attribute>) {
+    if !matches!(vis, Visibility::Public(_)) {
+        return;
+    }
+    let Some(Ok(predicate)) = Predicate::from_attributes(attrs) else {
+        return;
+    };
+    let idx = attrs
+        .iter()
+        .rposition(|attr| attr.path().is_ident("cfg"))
+        .unwrap();
+    attrs.insert(
+        idx + 1,
+        parse_quote!(#[cfg_attr(docsrs, doc(cfg(#predicate)))]),
+    );
+}
+
+impl VisitMut for DocCfgs {
+    fn visit_item_mut(&mut self, node: &mut Item) {
+        match node {
+            Item::Const(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            Item::Enum(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            Item::ExternCrate(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            Item::Fn(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            Item::Mod(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            Item::Static(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            Item::Struct(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            Item::Trait(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            Item::TraitAlias(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            Item::Type(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            Item::Union(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            Item::Use(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            _ => {}
+        }
+        visit_mut::visit_item_mut(self, node);
+    }
+
+    fn visit_impl_item_mut(&mut self, node: &mut ImplItem) {
+        match node {
+            ImplItem::Const(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            ImplItem::Fn(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            ImplItem::Type(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            _ => {}
+        }
+        visit_mut::visit_impl_item_mut(self, node);
+    }
+
+    fn visit_foreign_item_mut(&mut self, node: &mut ForeignItem) {
+        match node {
+            ForeignItem::Fn(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            ForeignItem::Static(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            ForeignItem::Type(item) => add_doc_cfg(&item.vis, &mut item.attrs),
+            _ => {}
+        }
+        visit_mut::visit_foreign_item_mut(self, node);
+    }
+}
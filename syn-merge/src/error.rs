@@ -0,0 +1,112 @@
+//! The error type returned when merging fails.
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use proc_macro2::Span;
+
+/// The kind of an [`Error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A source couldn't be parsed, see [`merge_sources`][crate::merge_sources].
+    Parse,
+    /// No inputs were given, or the input that was asked for doesn't exist.
+    NoInput,
+    /// The inputs are not the same node, and can't be merged into one.
+    ///
+    /// Lists like `Vec<syn::Item>` can always be merged, this only happens
+    /// when merging single values with [`merge`][crate::merge].
+    Mismatch,
+    /// A node differs between the inputs, but can't be `cfg`-guarded.
+    ///
+    /// This happens for e.g. verbatim tokens, unit fields, and `syn`
+    /// variants that this crate doesn't know about.
+    Unsupported,
+}
+
+/// An error that occurred while merging.
+///
+/// Contains the kind of error, and where possible the index of the input
+/// and the span of the node that caused it. Spans can't be compared, so
+/// errors are equal if their kind, message and input are.
+#[derive(Debug, Clone)]
+pub struct Error {
+    kind: ErrorKind,
+    message: String,
+    input: Option<usize>,
+    span: Option<Span>,
+}
+
+impl Error {
+    /// A new error, without an input or span.
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            input: None,
+            span: None,
+        }
+    }
+
+    pub(crate) fn no_input() -> Self {
+        Self::new(ErrorKind::NoInput, "no input")
+    }
+
+    /// Set the span of the offending node, if not already set.
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+
+    /// Set the index of the offending input, if not already set.
+    pub fn with_input(mut self, input: usize) -> Self {
+        self.input.get_or_insert(input);
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The message, without the input index.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The index of the input that caused the error, if known.
+    pub fn input(&self) -> Option<usize> {
+        self.input
+    }
+
+    /// The span of the node that caused the error, if known.
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+}
+
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.message == other.message && self.input == other.input
+    }
+}
+
+impl Eq for Error {}
+
+impl Hash for Error {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+        self.message.hash(state);
+        self.input.hash(state);
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(input) = self.input {
+            write!(f, "input {input}: ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,268 @@
+//! Move `cfg`-specific items into per-`cfg` submodules.
+//!
+//! Note: The generated modules glob-import their parent, so items inside can
+//! keep referring to shared items by name. Paths starting with `self::` or
+//! `super::` are not rewritten though.
+use std::collections::BTreeSet;
+use std::mem;
+
+use proc_macro2::TokenTree;
+use quote::{format_ident, ToTokens};
+use syn::{
+    parse_quote, Attribute, Fields, File, Ident, ImplItem, Item, Meta, Path, UseTree, Visibility,
+};
+
+use crate::overlap::item_names;
+use crate::Merge;
+
+struct Group {
+    key: String,
+    attr: Attribute,
+    items: Vec<Item>,
+}
+
+/// Hoist items that are only present under a single `cfg` into a generated
+/// `#[cfg(pred)] mod __pred { ... }`, and re-export them from the parent with
+/// `#[cfg(pred)] pub use __pred::*;`.
+///
+/// Shared items stay at the top level, the generated modules are emitted
+/// after them in the order their predicate first appeared. A module name
+/// that is already taken by an item or import gets a numeric suffix, like
+/// `__foo_2`.
+///
+/// Only items with exactly one `#[cfg]` attribute are moved. Imports, macros,
+/// `extern crate` items and modules are kept where they are, since moving
+/// them would change how paths inside them resolve. The items of inline
+/// modules are hoisted in the same way, into submodules of that module.
+///
+/// Private items, fields and inherent methods are made `pub(super)`, such
+/// that they stay accessible from the parent module.
+pub fn hoist_into_submodules(file: File) -> File {
+    File {
+        shebang: file.shebang,
+        attrs: file.attrs,
+        items: hoist_items(file.items),
+    }
+}
+
+fn hoist_items(items: Vec<Item>) -> Vec<Item> {
+    // The generated modules must not clash with anything in the module
+    let mut used_names: BTreeSet<String> = items.iter().flat_map(defined_names).collect();
+    let mut shared = Vec::new();
+    let mut groups: Vec<Group> = Vec::new();
+
+    for mut item in items {
+        if let Item::Mod(module) = &mut item {
+            if let Some((_, items)) = &mut module.content {
+                *items = hoist_items(mem::take(items));
+            }
+        }
+        let Some(idx) = hoistable_cfg(&item) else {
+            shared.push(item);
+            continue;
+        };
+        let attr = item.attrs_mut().unwrap().remove(idx);
+        let key = attr.meta.to_token_stream().to_string();
+        widen_item(&mut item);
+        match groups.iter_mut().find(|group| group.key == key) {
+            Some(group) => group.items.push(item),
+            None => groups.push(Group {
+                key,
+                attr,
+                items: vec![item],
+            }),
+        }
+    }
+
+    let mut items = shared;
+    for Group {
+        attr, items: inner, ..
+    } in groups
+    {
+        let name = module_name(&attr, &mut used_names);
+        items.push(parse_quote! {
+            #attr
+            mod #name {
+                use super::*;
+                #(#inner)*
+            }
+        });
+        items.push(parse_quote! {
+            #attr
+            pub use #name::*;
+        });
+    }
+
+    items
+}
+
+/// The index of the single `#[cfg]` attribute on the item, if the item can
+/// be moved into a submodule.
+fn hoistable_cfg(item: &Item) -> Option<usize> {
+    if !matches!(
+        item,
+        Item::Const(_)
+            | Item::Enum(_)
+            | Item::Fn(_)
+            | Item::ForeignMod(_)
+            | Item::Impl(_)
+            | Item::Static(_)
+            | Item::Struct(_)
+            | Item::Trait(_)
+            | Item::TraitAlias(_)
+            | Item::Type(_)
+            | Item::Union(_)
+    ) {
+        return None;
+    }
+    let mut cfgs = item
+        .attrs()
+        .iter()
+        .enumerate()
+        .filter(|(_, attr)| attr.path().is_ident("cfg"));
+    match (cfgs.next(), cfgs.next()) {
+        (Some((idx, _)), None) => Some(idx),
+        _ => None,
+    }
+}
+
+fn widen_item(item: &mut Item) {
+    match item {
+        Item::Const(item) => widen(&mut item.vis),
+        Item::Enum(item) => widen(&mut item.vis),
+        Item::Fn(item) => widen(&mut item.vis),
+        Item::ForeignMod(item) => {
+            for item in &mut item.items {
+                match item {
+                    syn::ForeignItem::Fn(item) => widen(&mut item.vis),
+                    syn::ForeignItem::Static(item) => widen(&mut item.vis),
+                    syn::ForeignItem::Type(item) => widen(&mut item.vis),
+                    _ => {}
+                }
+            }
+        }
+        // Trait implementations don't have visibility
+        Item::Impl(item) if item.trait_.is_none() => {
+            for item in &mut item.items {
+                match item {
+                    ImplItem::Const(item) => widen(&mut item.vis),
+                    ImplItem::Fn(item) => widen(&mut item.vis),
+                    ImplItem::Type(item) => widen(&mut item.vis),
+                    _ => {}
+                }
+            }
+        }
+        Item::Static(item) => widen(&mut item.vis),
+        Item::Struct(item) => {
+            widen(&mut item.vis);
+            widen_fields(&mut item.fields);
+        }
+        Item::Trait(item) => widen(&mut item.vis),
+        Item::TraitAlias(item) => widen(&mut item.vis),
+        Item::Type(item) => widen(&mut item.vis),
+        Item::Union(item) => {
+            widen(&mut item.vis);
+            for field in &mut item.fields.named {
+                widen(&mut field.vis);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn widen_fields(fields: &mut Fields) {
+    for field in fields {
+        widen(&mut field.vis);
+    }
+}
+
+/// Make the visibility relative to the parent module instead.
+fn widen(vis: &mut Visibility) {
+    match vis {
+        Visibility::Public(_) => {}
+        Visibility::Inherited => *vis = parse_quote!(pub(super)),
+        Visibility::Restricted(restricted) => {
+            let path = &*restricted.path;
+            let first = &path.segments[0].ident;
+            if path.leading_colon.is_some() || first == "crate" {
+                return;
+            }
+            let path: Path = if first == "self" {
+                let rest = path.segments.iter().skip(1);
+                parse_quote!(super #(::#rest)*)
+            } else {
+                parse_quote!(super::#path)
+            };
+            *vis = parse_quote!(pub(in #path));
+        }
+    }
+}
+
+/// The names that the item defines or imports.
+fn defined_names(item: &Item) -> Vec<String> {
+    fn use_names(tree: &UseTree, names: &mut Vec<String>) {
+        match tree {
+            UseTree::Path(path) => use_names(&path.tree, names),
+            UseTree::Name(name) => names.push(name.ident.to_string()),
+            UseTree::Rename(rename) => names.push(rename.rename.to_string()),
+            UseTree::Glob(_) => {}
+            UseTree::Group(group) => {
+                for tree in &group.items {
+                    use_names(tree, names);
+                }
+            }
+        }
+    }
+
+    let mut names: Vec<_> = item_names(item)
+        .into_iter()
+        .map(|(_, ident)| ident.to_string())
+        .collect();
+    if let Item::Use(item) = item {
+        use_names(&item.tree, &mut names);
+    }
+    names
+}
+
+/// Derive a module name like `__target_os_linux` from the predicate.
+fn module_name(attr: &Attribute, used_names: &mut BTreeSet<String>) -> Ident {
+    let mut parts = Vec::new();
+    if let Meta::List(list) = &attr.meta {
+        collect_name_parts(list.tokens.clone(), &mut parts);
+    }
+    let base = format!("__{}", parts.join("_"));
+    let mut name = base.clone();
+    let mut n = 2;
+    while !used_names.insert(name.clone()) {
+        name = format!("{base}_{n}");
+        n += 1;
+    }
+    format_ident!("{}", name)
+}
+
+fn collect_name_parts(tokens: proc_macro2::TokenStream, parts: &mut Vec<String>) {
+    for tt in tokens {
+        match tt {
+            TokenTree::Group(group) => collect_name_parts(group.stream(), parts),
+            TokenTree::Ident(ident) => parts.push(ident.to_string().to_lowercase()),
+            TokenTree::Literal(lit) => {
+                let s: String = lit
+                    .to_string()
+                    .chars()
+                    .filter(|c| *c != '"')
+                    .map(|c| {
+                        if c.is_ascii_alphanumeric() {
+                            c.to_ascii_lowercase()
+                        } else {
+                            '_'
+                        }
+                    })
+                    .collect();
+                if !s.is_empty() {
+                    parts.push(s);
+                }
+            }
+            TokenTree::Punct(_) => {}
+        }
+    }
+}
@@ -6,53 +6,180 @@
 compile_error!("The `std` feature currently must be enabled.");
 
 use multidiff::DiffableSequence;
-use quote::format_ident;
-use std::fmt;
-use syn::{parse_quote, punctuated::Punctuated, Attribute, File};
+use source_map::Origins;
+use specialize::specialize_within;
+use std::collections::BTreeSet;
+use std::path::Path;
+use syn::spanned::Spanned;
+use syn::{parse_quote, punctuated::Punctuated, Attribute, Expr, ExprLit, File, Lit, LitStr, Meta};
 
 #[macro_use]
 mod macros;
+mod aliases;
+#[cfg(feature = "pretty")]
+pub mod build;
+mod check_cfg;
+#[cfg(feature = "config")]
+mod config;
+mod diagnostic;
+mod doc_cfg;
+mod error;
+mod hoist;
+mod manifest;
+mod options;
+mod overlap;
+mod predicate;
+#[cfg(feature = "pretty")]
+mod preserve;
 mod proc_macro_impl;
+#[cfg(feature = "pretty")]
+mod report;
+mod simplify;
+mod source_map;
+mod specialize;
 mod syn_impl;
 mod syn_impl_generated;
 #[cfg(test)]
 mod tests;
-
-/// Errors on:
-/// - Differing shebangs.
-/// - No input files.
-/// - Unknown items (since we can't know for sure how to `cfg`-guard them).
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
-pub struct Error {
-    inner: String,
-}
-
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.inner)
-    }
+mod verify;
+
+pub use aliases::CfgAliases;
+pub use check_cfg::CheckCfg;
+#[cfg(feature = "config")]
+pub use config::{Config, ConfigError, ConfigInput, MergedOutput, Override, Style};
+pub use diagnostic::{Diagnostic, Location};
+pub use error::{Error, ErrorKind};
+pub use hoist::hoist_into_submodules;
+pub use manifest::{merge_files_with_manifest, Decision, Manifest, ManifestItem};
+pub use options::{MergeContext, MergeOptions};
+pub use overlap::{check_overlaps, Namespace, Overlap};
+pub use predicate::Predicate;
+#[cfg(feature = "pretty")]
+pub use preserve::{
+    apply_edits, merge_sources_as_edits, merge_sources_as_edits_with_options,
+    merge_sources_preserving, merge_sources_preserving_with_options, TextEdit,
+};
+#[cfg(feature = "pretty")]
+pub use report::html_report;
+pub use simplify::simplify_nested_cfgs;
+pub use source_map::{merge_files_with_source_map, MappedItem, Origin, SourceMap};
+pub use specialize::specialize;
+pub use verify::{verify, Normalization, VerifyError};
+
+#[cfg(feature = "derive")]
+pub use syn_merge_derive::Merge;
+
+// Used by `syn-merge-derive`
+#[doc(hidden)]
+pub mod __private {
+    pub use syn;
 }
 
-impl std::error::Error for Error {}
-
+// Allow `syn-merge-derive` to refer to `::syn_merge` in our own tests
+#[cfg(test)]
+extern crate self as syn_merge;
+
+/// A configuration that an input was generated for.
+///
+/// Consists of the predicate that is emitted in `#[cfg(...)]` attributes for
+/// items specific to this configuration, and the facts (names and key-value
+/// pairs) that hold in it, which are used when evaluating predicates.
+///
 /// Should support:
 /// - User-specified configs (e.g. flag `MYCRATE_XYZ` -> `feature = "xyz"`).
 /// - Automatically generated `rustc --print cfg`.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Cfgs {
-    s: String,
+    predicate: Predicate,
+    facts: BTreeSet<Predicate>,
 }
 
 impl Cfgs {
-    pub fn new(s: &str) -> Self {
-        Self { s: s.into() }
+    /// A configuration where the single name `name` holds, like `foo` for
+    /// `#[cfg(foo)]`.
+    ///
+    /// Use [`try_new`][Self::try_new] for other predicates.
+    pub fn new(name: &str) -> Self {
+        Self::from_predicate(Predicate::Name(name.into()))
     }
 
-    pub fn attribute(&self) -> Attribute {
-        let ident = format_ident!("{}", self.s);
-        parse_quote! {
-            #[cfg(#ident)]
+    /// Parse a configuration from a `cfg` predicate like `foo` or
+    /// `target_os = "linux"`, see [`from_predicate`][Self::from_predicate].
+    pub fn try_new(s: &str) -> syn::Result<Self> {
+        syn::parse_str(s).map(Self::from_predicate)
+    }
+
+    /// A configuration identified by `predicate`, in which `facts` hold.
+    ///
+    /// Fails if one of the facts isn't a name or a key-value pair.
+    pub fn with_facts(
+        predicate: Predicate,
+        facts: impl IntoIterator<Item = Predicate>,
+    ) -> syn::Result<Self> {
+        let facts = facts
+            .into_iter()
+            .map(|fact| match fact {
+                Predicate::Name(_) | Predicate::KeyValue(_, _) => Ok(fact),
+                _ => Err(syn::Error::new(
+                    proc_macro2::Span::call_site(),
+                    format!("expected a cfg name or key-value pair, found `{fact}`"),
+                )),
+            })
+            .collect::<syn::Result<_>>()?;
+        Ok(Self { predicate, facts })
+    }
+
+    /// A configuration identified by `predicate`, in which only the names
+    /// and key-value pairs that the predicate requires hold.
+    ///
+    /// E.g. `unix` and `target_os = "linux"` for
+    /// `all(unix, target_os = "linux")`.
+    pub fn from_predicate(predicate: Predicate) -> Self {
+        fn required(predicate: &Predicate) -> Vec<Predicate> {
+            match predicate {
+                Predicate::Name(_) | Predicate::KeyValue(_, _) => vec![predicate.clone()],
+                Predicate::All(preds) => preds.iter().flat_map(required).collect(),
+                _ => Vec::new(),
+            }
         }
+        let facts = required(&predicate).into_iter().collect();
+        Self { predicate, facts }
+    }
+
+    /// A configuration identified by `predicate`, in which the facts printed
+    /// by `rustc --print cfg` hold, one per line like `target_os="linux"`.
+    pub fn from_print_cfg(predicate: Predicate, output: &str) -> syn::Result<Self> {
+        let facts = output
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(syn::parse_str)
+            .collect::<syn::Result<Vec<Predicate>>>()?;
+        Self::with_facts(predicate, facts)
+    }
+
+    pub fn predicate(&self) -> &Predicate {
+        &self.predicate
+    }
+
+    pub fn facts(&self) -> impl Iterator<Item = &Predicate> {
+        self.facts.iter()
+    }
+
+    pub fn attribute(&self) -> Attribute {
+        self.predicate.attribute()
+    }
+
+    /// Whether the name or key-value pair holds in this configuration.
+    pub fn holds(&self, fact: &Predicate) -> bool {
+        self.facts.contains(fact)
+    }
+
+    /// The names and key-value pairs in the predicate and the facts.
+    pub(crate) fn atoms(&self) -> BTreeSet<&Predicate> {
+        let mut atoms: BTreeSet<&Predicate> = self.facts.iter().collect();
+        self.predicate.collect_atoms(&mut atoms);
+        atoms
     }
 }
 
@@ -61,23 +188,55 @@ impl Cfgs {
 // ForeignItemMacro::mac
 // ImplItemMacro::mac
 
-pub trait Merge: Clone + Sized {
-    fn top_level_eq(&self, other: &Self) -> bool {
-        let _ = other;
-        todo!()
-    }
-
-    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(iter: I) -> Self
+/// A node that can be merged.
+///
+/// The children of a node should be compared, merged and given attributes
+/// through the [`MergeContext`], so that user callbacks are respected.
+///
+/// Nodes that can't be merged or `cfg`-guarded return an [`Error`] instead
+/// of panicking.
+pub trait Merge: Clone + Sized + 'static {
+    fn top_level_eq(&self, other: &Self, cx: &MergeContext<'_>) -> bool;
+
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(
+        iter: I,
+        cx: &MergeContext<'_>,
+    ) -> Result<Self, Error>
     where
         Self: 'a,
         I::IntoIter: Clone,
     {
+        let _ = cx;
         merge_by_extracting_first(iter)
     }
 
-    fn add_attr(&mut self, attr: Attribute) {
-        let _ = attr;
-        unimplemented!()
+    fn add_attr(&mut self, attr: Attribute, cx: &MergeContext<'_>) -> Result<(), Error> {
+        let _ = (attr, cx);
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("can't add `#[cfg]` to `{}`", std::any::type_name::<Self>()),
+        ))
+    }
+
+    /// Merge lists of this type, e.g. the `Vec<Self>` in each input.
+    ///
+    /// Types can override this to change how their lists are lined up.
+    fn merge_list(input: &[(&[Self], &Cfgs)], cx: &MergeContext<'_>) -> Result<Vec<Self>, Error> {
+        let tmp: Vec<_> = input
+            .iter()
+            .map(|(values, cfgs)| WithCfgs { values, cfgs, cx })
+            .collect();
+        merge_recursively(&tmp)
+    }
+
+    /// The attributes on this node, if it can have any.
+    fn attrs(&self) -> &[Attribute] {
+        &[]
+    }
+
+    /// Mutable access to the attributes on this node, if it can have any.
+    fn attrs_mut(&mut self) -> Option<&mut Vec<Attribute>> {
+        None
     }
 }
 
@@ -87,223 +246,560 @@ pub(crate) fn merge_by_extracting_first<
     I: IntoIterator<Item = (&'a T, &'a Cfgs)>,
 >(
     iter: I,
-) -> T {
-    let (item, _cfgs) = iter.into_iter().next().unwrap();
-    item.clone()
+) -> Result<T, Error> {
+    Ok(first(iter)?.clone())
+}
+
+/// The first value, which all the others are `top_level_eq` to.
+pub(crate) fn first<'a, T: 'a, I: IntoIterator<Item = (&'a T, &'a Cfgs)>>(
+    iter: I,
+) -> Result<&'a T, Error> {
+    let (first, _cfgs) = iter.into_iter().next().ok_or_else(Error::no_input)?;
+    Ok(first)
 }
 
 impl<T: Merge> Merge for Box<T> {
-    fn top_level_eq(&self, other: &Self) -> bool {
-        (**self).top_level_eq(other)
+    fn top_level_eq(&self, other: &Self, cx: &MergeContext<'_>) -> bool {
+        cx.top_level_eq(&**self, &**other)
     }
 
-    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(iter: I) -> Self
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(
+        iter: I,
+        cx: &MergeContext<'_>,
+    ) -> Result<Self, Error>
     where
         Self: 'a,
         I::IntoIter: Clone,
     {
-        Box::new(T::merge(iter.into_iter().map(|(t, cfgs)| (&**t, cfgs))))
+        cx.merge(iter.into_iter().map(|(t, cfgs)| (&**t, cfgs)))
+            .map(Box::new)
+    }
+
+    fn add_attr(&mut self, attr: Attribute, cx: &MergeContext<'_>) -> Result<(), Error> {
+        cx.add_attr(&mut **self, attr)
     }
 
-    fn add_attr(&mut self, attr: Attribute) {
-        (**self).add_attr(attr)
+    fn attrs(&self) -> &[Attribute] {
+        (**self).attrs()
+    }
+
+    fn attrs_mut(&mut self) -> Option<&mut Vec<Attribute>> {
+        (**self).attrs_mut()
     }
 }
 
 impl<T: Merge> Merge for Option<T> {
-    fn top_level_eq(&self, other: &Self) -> bool {
+    fn top_level_eq(&self, other: &Self, cx: &MergeContext<'_>) -> bool {
         match (self, other) {
-            (Some(this), Some(other)) => this.top_level_eq(other),
+            (Some(this), Some(other)) => cx.top_level_eq(this, other),
             (None, None) => true,
             _ => false,
         }
     }
 
-    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(iter: I) -> Self
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(
+        iter: I,
+        cx: &MergeContext<'_>,
+    ) -> Result<Self, Error>
     where
         Self: 'a,
         I::IntoIter: Clone,
     {
-        todo!()
+        let iter = iter.into_iter();
+        // All values are either `Some` or `None`, since they're `top_level_eq`
+        if first(iter.clone())?.is_none() {
+            return Ok(None);
+        }
+        cx.merge(iter.filter_map(|(this, cfgs)| this.as_ref().map(|this| (this, cfgs))))
+            .map(Some)
     }
 
-    fn add_attr(&mut self, attr: Attribute) {
+    fn add_attr(&mut self, attr: Attribute, cx: &MergeContext<'_>) -> Result<(), Error> {
         match self {
-            Some(this) => this.add_attr(attr),
-            None => unimplemented!(),
+            Some(this) => cx.add_attr(this, attr),
+            None => Err(Error::new(
+                ErrorKind::Unsupported,
+                "can't add `#[cfg]` to a missing value",
+            )),
         }
     }
 }
 
 impl<T: Merge, U: Merge> Merge for (T, U) {
-    fn top_level_eq(&self, other: &Self) -> bool {
-        self.0.top_level_eq(&other.0) && self.1.top_level_eq(&other.1)
+    fn top_level_eq(&self, other: &Self, cx: &MergeContext<'_>) -> bool {
+        cx.top_level_eq(&self.0, &other.0) && cx.top_level_eq(&self.1, &other.1)
     }
 
-    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(iter: I) -> Self
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(
+        iter: I,
+        cx: &MergeContext<'_>,
+    ) -> Result<Self, Error>
     where
         Self: 'a,
         I::IntoIter: Clone,
     {
-        todo!()
+        let iter = iter.into_iter();
+        Ok((
+            cx.merge(iter.clone().map(|((t, _), cfgs)| (t, cfgs)))?,
+            cx.merge(iter.map(|((_, u), cfgs)| (u, cfgs)))?,
+        ))
     }
 
-    fn add_attr(&mut self, attr: Attribute) {
-        self.0.add_attr(attr.clone());
-        self.1.add_attr(attr);
+    fn add_attr(&mut self, attr: Attribute, cx: &MergeContext<'_>) -> Result<(), Error> {
+        cx.add_attr(&mut self.0, attr.clone())?;
+        cx.add_attr(&mut self.1, attr)
     }
 }
 
 impl<T: Merge, U: Merge, V: Merge> Merge for (T, U, V) {
-    fn top_level_eq(&self, other: &Self) -> bool {
-        self.0.top_level_eq(&other.0)
-            && self.1.top_level_eq(&other.1)
-            && self.2.top_level_eq(&other.2)
+    fn top_level_eq(&self, other: &Self, cx: &MergeContext<'_>) -> bool {
+        cx.top_level_eq(&self.0, &other.0)
+            && cx.top_level_eq(&self.1, &other.1)
+            && cx.top_level_eq(&self.2, &other.2)
     }
 
-    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(iter: I) -> Self
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(
+        iter: I,
+        cx: &MergeContext<'_>,
+    ) -> Result<Self, Error>
     where
         Self: 'a,
         I::IntoIter: Clone,
     {
-        todo!()
+        let iter = iter.into_iter();
+        Ok((
+            cx.merge(iter.clone().map(|((t, _, _), cfgs)| (t, cfgs)))?,
+            cx.merge(iter.clone().map(|((_, u, _), cfgs)| (u, cfgs)))?,
+            cx.merge(iter.map(|((_, _, v), cfgs)| (v, cfgs)))?,
+        ))
     }
 
-    fn add_attr(&mut self, attr: Attribute) {
-        self.0.add_attr(attr.clone());
-        self.1.add_attr(attr.clone());
-        self.2.add_attr(attr);
+    fn add_attr(&mut self, attr: Attribute, cx: &MergeContext<'_>) -> Result<(), Error> {
+        cx.add_attr(&mut self.0, attr.clone())?;
+        cx.add_attr(&mut self.1, attr.clone())?;
+        cx.add_attr(&mut self.2, attr)
     }
 }
 
-// TODO: Implement this properly
 impl Merge for Attribute {
-    fn top_level_eq(&self, other: &Self) -> bool {
+    fn top_level_eq(&self, other: &Self, _cx: &MergeContext<'_>) -> bool {
         self == other
     }
 
-    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(iter: I) -> Self
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(
+        iter: I,
+        _cx: &MergeContext<'_>,
+    ) -> Result<Self, Error>
     where
         Self: 'a,
         I::IntoIter: Clone,
     {
-        parse_quote! {
-            #[cfg(todo)]
+        merge_by_extracting_first(iter)
+    }
+
+    /// Doc comments that differ between the inputs are split into lines,
+    /// so that only the differing lines become `#[cfg_attr(pred, doc = ...)]`.
+    fn merge_list(input: &[(&[Self], &Cfgs)], cx: &MergeContext<'_>) -> Result<Vec<Self>, Error> {
+        let docs = |attrs: &[Self]| -> Vec<Self> {
+            attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("doc"))
+                .cloned()
+                .collect()
+        };
+        let differs = match input.first() {
+            Some((first, _)) => {
+                let first = docs(first);
+                input.iter().any(|(attrs, _)| docs(attrs) != first)
+            }
+            None => false,
+        };
+        if !differs {
+            let tmp: Vec<_> = input
+                .iter()
+                .map(|(values, cfgs)| WithCfgs { values, cfgs, cx })
+                .collect();
+            return merge_recursively(&tmp);
         }
+        let split: Vec<(Vec<Self>, &Cfgs)> = input
+            .iter()
+            .map(|(attrs, cfgs)| (attrs.iter().flat_map(split_doc_lines).collect(), *cfgs))
+            .collect();
+        let tmp: Vec<_> = split
+            .iter()
+            .map(|(values, cfgs)| WithCfgs { values, cfgs, cx })
+            .collect();
+        merge_recursively(&tmp)
     }
 
-    fn add_attr(&mut self, attr: Attribute) {
-        todo!()
+    /// Attributes can't have attributes, so we turn `#[attr]` into
+    /// `#[cfg_attr(pred, attr)]` instead.
+    fn add_attr(&mut self, attr: Attribute, _cx: &MergeContext<'_>) -> Result<(), Error> {
+        let Meta::List(cfg) = &attr.meta else {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "only `#[cfg(...)]` can be added to attributes",
+            )
+            .with_span(self.span()));
+        };
+        let pred = &cfg.tokens;
+        let meta = &self.meta;
+        self.meta = parse_quote!(cfg_attr(#pred, #meta));
+        Ok(())
     }
 }
 
 impl<T: Merge> Merge for Vec<T> {
-    fn top_level_eq(&self, _other: &Self) -> bool {
+    fn top_level_eq(&self, _other: &Self, _cx: &MergeContext<'_>) -> bool {
         true
     }
 
-    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(iter: I) -> Self
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(
+        iter: I,
+        cx: &MergeContext<'_>,
+    ) -> Result<Self, Error>
     where
         Self: 'a,
         I::IntoIter: Clone,
     {
-        let tmp: Vec<_> = iter
+        let input: Vec<(&[T], &Cfgs)> = iter
             .into_iter()
-            .map(|(values, cfgs)| WithCfgs {
-                values: &**values,
-                cfgs,
-            })
+            .map(|(values, cfgs)| (&**values, cfgs))
             .collect();
-        merge_recursively(&tmp)
+        T::merge_list(&input, cx)
     }
 
-    fn add_attr(&mut self, attr: Attribute) {
+    fn add_attr(&mut self, attr: Attribute, cx: &MergeContext<'_>) -> Result<(), Error> {
         for item in self {
-            item.add_attr(attr.clone());
+            cx.add_attr(item, attr.clone())?;
         }
+        Ok(())
     }
 }
 
-impl<T: Merge, P: PartialEq + Clone> Merge for Punctuated<T, P> {
-    fn top_level_eq(&self, _other: &Self) -> bool {
+impl<T: Merge, P: PartialEq + Clone + Default + 'static> Merge for Punctuated<T, P> {
+    fn top_level_eq(&self, _other: &Self, _cx: &MergeContext<'_>) -> bool {
         true
     }
 
-    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(iter: I) -> Self
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(
+        iter: I,
+        cx: &MergeContext<'_>,
+    ) -> Result<Self, Error>
     where
         Self: 'a,
         I::IntoIter: Clone,
     {
-        let _ = iter;
-        todo!("implement this properly")
+        let iter = iter.into_iter();
+        let first = first(iter.clone())?;
+        let values: Vec<(Vec<T>, &Cfgs)> = iter
+            .map(|(values, cfgs)| (values.iter().cloned().collect(), cfgs))
+            .collect();
+        let input: Vec<(&[T], &Cfgs)> = values
+            .iter()
+            .map(|(values, cfgs)| (&**values, *cfgs))
+            .collect();
+        let mut res: Self = T::merge_list(&input, cx)?.into_iter().collect();
+        if first.trailing_punct() && !res.empty_or_trailing() {
+            res.push_punct(P::default());
+        }
+        Ok(res)
     }
 
-    fn add_attr(&mut self, attr: Attribute) {
+    fn add_attr(&mut self, attr: Attribute, cx: &MergeContext<'_>) -> Result<(), Error> {
         for item in self {
-            item.add_attr(attr.clone());
+            cx.add_attr(item, attr.clone())?;
         }
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+/// Split a multi-line `#[doc = "..."]` into one attribute per line.
+fn split_doc_lines(attr: &Attribute) -> Vec<Attribute> {
+    let Meta::NameValue(doc) = &attr.meta else {
+        return vec![attr.clone()];
+    };
+    let Expr::Lit(ExprLit {
+        lit: Lit::Str(lit), ..
+    }) = &doc.value
+    else {
+        return vec![attr.clone()];
+    };
+    let value = lit.value();
+    if !doc.path.is_ident("doc") || !value.contains('\n') {
+        return vec![attr.clone()];
+    }
+    value
+        .split('\n')
+        .map(|line| {
+            let line = LitStr::new(line, lit.span());
+            Attribute {
+                meta: parse_quote!(doc = #line),
+                ..attr.clone()
+            }
+        })
+        .collect()
+}
+
 struct WithCfgs<'a, T> {
     values: &'a [T],
     cfgs: &'a Cfgs,
+    cx: &'a MergeContext<'a>,
 }
 
 impl<'a, T: Merge> DiffableSequence for WithCfgs<'a, T> {
-    type Item = &'a T;
+    type Item = (&'a T, &'a MergeContext<'a>);
 
-    fn eq(a: &Self::Item, b: &Self::Item) -> bool {
-        a.top_level_eq(b)
+    fn eq((a, cx): &Self::Item, (b, _): &Self::Item) -> bool {
+        cx.top_level_eq(*a, *b)
     }
 
     fn get_iter(&self) -> impl Iterator<Item = Self::Item> {
-        self.values.iter()
+        let cx = self.cx;
+        self.values.iter().map(move |value| (value, cx))
     }
 }
 
-pub(crate) fn merge_recursively<T: Merge>(input: &[WithCfgs<'_, T>]) -> Vec<T> {
+pub(crate) fn merge_recursively<T: Merge>(input: &[WithCfgs<'_, T>]) -> Result<Vec<T>, Error> {
     multidiff::multidiff_indexes(input)
         .into_iter()
         .map(|indexes| {
-            let iter = indexes.iter().zip(input).filter_map(|(idx, with_cfgs)| {
-                idx.map(|idx| (&with_cfgs.values[idx], with_cfgs.cfgs))
-            });
-
-            let cfgs: Vec<_> = iter.clone().map(|(_, cfgs)| cfgs).collect();
-
-            let mut t = T::merge(iter);
-
-            // If it appears in all, just output the item
-            if cfgs.len() == input.len() {
-                t
+            let values: Vec<_> = indexes
+                .iter()
+                .zip(input)
+                .filter_map(|(idx, with_cfgs)| {
+                    idx.map(|idx| (&with_cfgs.values[idx], with_cfgs.cfgs))
+                })
+                .collect();
+
+            // Items are matched ignoring their own `cfg`s, and merged without
+            // them, since they're combined with the input's predicate below.
+            // Items with a `cfg` that can't be parsed keep all of their
+            // `cfg`s instead.
+            let own: Vec<_> = values
+                .iter()
+                .map(
+                    |(value, _)| match Predicate::from_attributes(value.attrs()) {
+                        Some(Ok(own)) => Some(own),
+                        Some(Err(_)) => None,
+                        None => Some(Predicate::TRUE),
+                    },
+                )
+                .collect();
+            let cx = input[0].cx;
+            // Errors that aren't tied to a single input are blamed on the
+            // first input that the node is in.
+            let blame = |err: Error| match values.first().and_then(|(_, cfgs)| cx.input_index(cfgs))
+            {
+                Some(idx) => err.with_input(idx),
+                None => err,
+            };
+            let mut t = if own.iter().flatten().all(Predicate::is_true) {
+                cx.merge(values.iter().copied())
             } else {
-                for cfgs in cfgs {
-                    t.add_attr(cfgs.attribute());
+                let stripped: Vec<_> = values
+                    .iter()
+                    .zip(&own)
+                    .map(|((value, cfgs), own)| match own {
+                        Some(_) => (strip_cfgs((*value).clone()), *cfgs),
+                        None => ((*value).clone(), *cfgs),
+                    })
+                    .collect();
+                cx.merge(stripped.iter().map(|(value, cfgs)| (value, *cfgs)))
+            }
+            .map_err(blame)?;
+            let own: Vec<_> = own
+                .into_iter()
+                .map(|own| own.unwrap_or(Predicate::TRUE))
+                .collect();
+
+            let predicate = if own.iter().all(|this| *this == own[0]) {
+                // If it appears in all, the item's own `cfg` is enough
+                if values.len() == input.len() {
+                    own[0].clone()
+                } else {
+                    let inputs = values.iter().map(|(_, cfgs)| cfgs.predicate.clone());
+                    Predicate::All(vec![Predicate::Any(inputs.collect()), own[0].clone()])
                 }
-                t
+            } else {
+                let inputs = values
+                    .iter()
+                    .zip(own)
+                    .map(|((_, cfgs), own)| Predicate::All(vec![cfgs.predicate.clone(), own]));
+                Predicate::Any(inputs.collect())
+            };
+            let predicate = predicate.simplify();
+            if !predicate.is_true() {
+                cx.add_attr(&mut t, predicate.attribute()).map_err(blame)?;
+            }
+            if let Some(origins) = cx.origins() {
+                origins.record(&mut t, &values, cx);
             }
+            Ok(t)
         })
         .collect()
 }
 
+/// Remove the `#[cfg]` attributes that we understand from the node.
+fn strip_cfgs<T: Merge>(mut value: T) -> T {
+    if let Some(attrs) = value.attrs_mut() {
+        attrs.retain(|attr| !matches!(Predicate::from_attribute(attr), Some(Ok(_))));
+    }
+    value
+}
+
+/// Merge a value from each input into a single value.
+///
+/// This works for any [`Merge`] type, e.g. `Vec<syn::Item>`, `syn::ItemImpl`
+/// or `syn::Expr`. Lists like `Vec<syn::Item>` are lined up and the parts
+/// that are specific to some inputs are `cfg`-guarded, as in
+/// [`merge_files`]. Other values must be mergeable into a single value, which
+/// roughly means that they must be of the same kind with the same name.
+/// Functions only match if their bodies are equal, but blocks passed here
+/// directly have their statements lined up like items.
+///
+/// The order in which the inputs are passed influences the output.
+pub fn merge<T: Merge>(input: &[(T, Cfgs)]) -> Result<T, Error> {
+    merge_with_options(input, &MergeOptions::new())
+}
+
+/// Like [`merge`], but with the given options.
+///
+/// Only the callbacks in the options are used, the rest only apply to files.
+pub fn merge_with_options<T: Merge>(
+    input: &[(T, Cfgs)],
+    options: &MergeOptions,
+) -> Result<T, Error> {
+    let inputs: Vec<_> = input.iter().map(|(_, cfgs)| cfgs).collect();
+    let cx = MergeContext::new(options).with_inputs(&inputs);
+    let Some((first, _)) = input.first() else {
+        return Err(Error::no_input());
+    };
+    if let Some(idx) = input
+        .iter()
+        .position(|(value, _)| !cx.top_level_eq(first, value))
+    {
+        return Err(
+            Error::new(ErrorKind::Mismatch, "can't be merged with input 0").with_input(idx),
+        );
+    }
+    cx.merge(input.iter().map(|(value, cfgs)| (value, cfgs)))
+}
+
 /// The order in which the files are passed influences the output.
 pub fn merge_files(input: &[(File, Cfgs)]) -> Result<File, Error> {
+    merge_files_with_options(input, &MergeOptions::new())
+}
+
+/// Like [`merge_files`], but with the given options.
+pub fn merge_files_with_options(
+    input: &[(File, Cfgs)],
+    options: &MergeOptions,
+) -> Result<File, Error> {
+    merge_files_impl(input, options, None)
+}
+
+/// Merge the files, recording where the items came from in `origins`.
+pub(crate) fn merge_files_impl(
+    input: &[(File, Cfgs)],
+    options: &MergeOptions,
+    origins: Option<&Origins>,
+) -> Result<File, Error> {
+    if input.is_empty() {
+        return Err(Error::no_input());
+    }
+    let inputs: Vec<_> = input.iter().map(|(_, cfgs)| cfgs).collect();
+    let cx = MergeContext::new(options)
+        .with_origins(origins)
+        .with_inputs(&inputs);
     let items: Vec<_> = input
         .iter()
         .map(|(file, cfgs)| WithCfgs {
             values: &file.items,
             cfgs,
+            cx: &cx,
         })
         .collect();
 
-    let combined = merge_recursively(&items);
+    let mut combined = merge_recursively(&items)?;
+
+    if let Some(message) = &options.compile_error {
+        let inputs = input.iter().map(|(_, cfgs)| cfgs.predicate.clone());
+        let unsupported = Predicate::Any(inputs.collect())
+            .negate()
+            .simplify_under(&options.universe);
+        if !unsupported.is_false() {
+            combined.push(parse_quote! {
+                #[cfg(#unsupported)]
+                compile_error!(#message);
+            });
+        }
+    }
 
-    Ok(File {
+    let mut file = File {
         shebang: input[0].0.shebang.clone(),
         // TODO: Merge attributes
         attrs: input[0].0.attrs.clone(),
         items: combined,
-    })
+    };
+    if options.minimize || !options.universe.is_true() {
+        simplify::simplify_cfgs_under(&mut file, options.universe.clone(), options.minimize);
+    }
+    if options.doc_cfg {
+        doc_cfg::add_doc_cfgs(&mut file);
+    }
+    Ok(file)
+}
+
+/// Parse and merge the source code of each input, e.g. the contents of a
+/// file at `path`.
+///
+/// Unlike [`merge_files`], errors point into the source that caused them,
+/// and render like `rustc` diagnostics.
+pub fn merge_sources<P: AsRef<Path>>(input: &[(P, &str, Cfgs)]) -> Result<File, Diagnostic> {
+    merge_sources_with_options(input, &MergeOptions::new())
+}
+
+/// Like [`merge_sources`], but with the given options.
+pub fn merge_sources_with_options<P: AsRef<Path>>(
+    input: &[(P, &str, Cfgs)],
+    options: &MergeOptions,
+) -> Result<File, Diagnostic> {
+    let files = parse_sources(input)?;
+    merge_files_with_options(&files, options).map_err(|err| Diagnostic::merge(&err, input))
+}
+
+/// Parse the source of each input, with span locations.
+pub(crate) fn parse_sources<P: AsRef<Path>>(
+    input: &[(P, &str, Cfgs)],
+) -> Result<Vec<(File, Cfgs)>, Diagnostic> {
+    input
+        .iter()
+        .enumerate()
+        .map(
+            |(idx, (path, source, cfgs))| match syn::parse_file(source) {
+                Ok(file) => Ok((file, cfgs.clone())),
+                Err(err) => Err(Diagnostic::parse(idx, path.as_ref(), source, &err)),
+            },
+        )
+        .collect()
+}
+
+/// Merge another input into an already merged file.
+///
+/// `configs` are the configurations that `merged` was created from. The
+/// `#[cfg]`s in `merged` are evaluated for each of them to find out which
+/// items were present in which configuration, after which `input` is merged
+/// in as if it had been passed last to [`merge_files`] together with the
+/// original inputs.
+pub fn merge_into(merged: &File, configs: &[Cfgs], input: &(File, Cfgs)) -> Result<File, Error> {
+    // Only evaluate what the configurations decide, so that items with
+    // `cfg`s of their own keep them
+    let all_configs: Vec<_> = configs.iter().chain(Some(&input.1)).cloned().collect();
+    let mut inputs: Vec<_> = configs
+        .iter()
+        .map(|cfgs| (specialize_within(merged, cfgs, &all_configs), cfgs.clone()))
+        .collect();
+    inputs.push(input.clone());
+    merge_files(&inputs)
 }
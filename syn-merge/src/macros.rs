@@ -1,21 +1,30 @@
 macro_rules! impl_merge_eq {
     ($(<($generics:ident),*>)? $ty:ty) => {
         impl $(<($generics: PartialEq),*>)? crate::Merge for $ty {
-            fn top_level_eq(&self, other: &Self) -> bool {
+            fn top_level_eq(&self, other: &Self, _cx: &crate::MergeContext<'_>) -> bool {
                 self == other
             }
 
-            fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a crate::Cfgs)>>(iter: I) -> Self
+            fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a crate::Cfgs)>>(
+                iter: I,
+                _cx: &crate::MergeContext<'_>,
+            ) -> Result<Self, crate::Error>
             where
                 Self: 'a,
                 I::IntoIter: Clone,
             {
-                let (item, _cfgs) = iter.into_iter().next().unwrap();
-                item.clone()
+                crate::merge_by_extracting_first(iter)
             }
 
-            fn add_attr(&mut self, _attr: crate::Attribute) {
-                unreachable!()
+            fn add_attr(
+                &mut self,
+                _attr: crate::Attribute,
+                _cx: &crate::MergeContext<'_>,
+            ) -> Result<(), crate::Error> {
+                Err(crate::Error::new(
+                    crate::ErrorKind::Unsupported,
+                    concat!("can't add `#[cfg]` to `", stringify!($ty), "`"),
+                ))
             }
         }
     };
@@ -29,53 +38,150 @@ macro_rules! impl_merge_enum {
         }
     ) => {
         impl crate::Merge for $ty {
-            fn top_level_eq(&self, other: &Self) -> bool {
+            fn top_level_eq(&self, other: &Self, _cx: &crate::MergeContext<'_>) -> bool {
                 match (self, other) {
                     $(
-                        (Self::$variant(this), Self::$variant(other)) => this.top_level_eq(other),
+                        (Self::$variant(this), Self::$variant(other)) => _cx.top_level_eq(this, other),
                     )*
                     _ => false,
                 }
             }
 
-            fn add_attr(&mut self, _attr: crate::Attribute) {
+            fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a crate::Cfgs)>>(
+                iter: I,
+                _cx: &crate::MergeContext<'_>,
+            ) -> Result<Self, crate::Error>
+            where
+                Self: 'a,
+                I::IntoIter: Clone,
+            {
+                let iter = iter.into_iter();
+                // All values are the same variant, since they're `top_level_eq`
+                let first = crate::first(iter.clone())?;
+                match first {
+                    $(
+                        Self::$variant(_) => {
+                            // Collected to avoid infinitely nested iterator
+                            // types in recursive structures like `Expr`.
+                            let values: Vec<_> = iter
+                                .filter_map(|(this, cfgs)| match this {
+                                    Self::$variant(this) => Some((this, cfgs)),
+                                    _ => None,
+                                })
+                                .collect();
+                            _cx.merge(values).map(Self::$variant)
+                        }
+                    )*
+                    $(_ => Ok(first.clone()) $comma)?
+                }
+            }
+
+            fn add_attr(
+                &mut self,
+                _attr: crate::Attribute,
+                _cx: &crate::MergeContext<'_>,
+            ) -> Result<(), crate::Error> {
+                match self {
+                    $(
+                        Self::$variant(item) => _cx.add_attr(item, _attr),
+                    )*
+                    $(_ => Err(crate::Error::new(
+                        crate::ErrorKind::Unsupported,
+                        concat!("can't add `#[cfg]` to unknown `", stringify!($ty), "` variant"),
+                    )) $comma)?
+                }
+            }
+
+            fn attrs(&self) -> &[crate::Attribute] {
+                match self {
+                    $(
+                        Self::$variant(item) => item.attrs(),
+                    )*
+                    $(_ => &[] $comma)?
+                }
+            }
+
+            fn attrs_mut(&mut self) -> Option<&mut Vec<crate::Attribute>> {
                 match self {
                     $(
-                        Self::$variant(item) => item.add_attr(_attr),
+                        Self::$variant(item) => item.attrs_mut(),
                     )*
-                    $(_ => unimplemented!() $comma)?
+                    $(_ => None $comma)?
                 }
             }
         }
     };
 }
 
+/// Push the attribute to the given field, or fail if the struct has no
+/// attributes.
+macro_rules! add_attr_to_field {
+    ($self:ident, $attr:ident, $ty:ty, $attrs:ident) => {{
+        $self.$attrs.push($attr);
+        Ok(())
+    }};
+    ($self:ident, $attr:ident, $ty:ty,) => {
+        Err(crate::Error::new(
+            crate::ErrorKind::Unsupported,
+            concat!("can't add `#[cfg]` to `", stringify!($ty), "`"),
+        ))
+    };
+}
+
+/// Compare a field for `top_level_eq`, either recursively or, when marked
+/// with `#[eq]`, as a whole.
+macro_rules! field_top_level_eq {
+    ($cx:ident, $this:ident, $other:ident, $field:ident) => {
+        $cx.top_level_eq(&$this.$field, &$other.$field)
+    };
+    ($cx:ident, $this:ident, $other:ident, $field:ident, eq) => {
+        $this.$field == $other.$field
+    };
+}
+
 macro_rules! impl_merge_struct {
     (
         $(#[$attrs:ident])?
         $ty:ty {
-            $($field:ident),* $(,)?
+            $($(#[$eq:ident])? $field:ident),* $(,)?
         }
     ) => {
         impl crate::Merge for $ty {
-            fn top_level_eq(&self, other: &Self) -> bool {
-                true $(&& self.$field.top_level_eq(&other.$field))*
+            fn top_level_eq(&self, other: &Self, cx: &crate::MergeContext<'_>) -> bool {
+                true $(&& field_top_level_eq!(cx, self, other, $field $(, $eq)?))*
             }
 
-            fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a crate::Cfgs)>>(iter: I) -> Self
+            fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a crate::Cfgs)>>(
+                iter: I,
+                cx: &crate::MergeContext<'_>,
+            ) -> Result<Self, crate::Error>
             where
                 Self: 'a,
                 I::IntoIter: Clone,
             {
                 let iter = iter.into_iter();
-                Self {
-                    $($field: crate::Merge::merge(iter.clone().map(|(Self { $field, .. }, cfgs)| ($field, cfgs))),)*
-                }
+                Ok(Self {
+                    $($field: cx.merge(iter.clone().map(|(Self { $field, .. }, cfgs)| ($field, cfgs)))?,)*
+                })
             }
 
-            fn add_attr(&mut self, _attr: crate::Attribute) {
-                $(self.$attrs.push(_attr);)?
+            fn add_attr(
+                &mut self,
+                _attr: crate::Attribute,
+                _cx: &crate::MergeContext<'_>,
+            ) -> Result<(), crate::Error> {
+                add_attr_to_field!(self, _attr, $ty, $($attrs)?)
             }
+
+            $(
+                fn attrs(&self) -> &[crate::Attribute] {
+                    &self.$attrs
+                }
+
+                fn attrs_mut(&mut self) -> Option<&mut Vec<crate::Attribute>> {
+                    Some(&mut self.$attrs)
+                }
+            )?
         }
     };
 }
@@ -0,0 +1,199 @@
+//! A machine-readable report of how each item was merged.
+use std::slice;
+
+use syn::File;
+
+use crate::source_map::{merge_and_collect, AnyNode, Node};
+use crate::verify::{first_difference, Describe, Difference};
+use crate::{Cfgs, Error, MappedItem, MergeOptions, Origin, SourceMap};
+
+/// How each item of a merged file was merged, see
+/// [`merge_files_with_manifest`].
+///
+/// With the `json` feature, this can be written to and read from JSON, e.g.
+/// to compare against the manifest of an earlier release with
+/// [`newly_specific`][Self::newly_specific].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Manifest {
+    /// The number of inputs.
+    pub inputs: usize,
+    /// The items, with each item followed by its children.
+    pub items: Vec<ManifestItem>,
+}
+
+/// An item in the merged file, see [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ManifestItem {
+    /// The indices leading to the item, see
+    /// [`MappedItem::path`][crate::MappedItem::path].
+    pub path: Vec<usize>,
+    /// The descriptions of the item and its parents, like
+    /// `["mod sys", "fn foo"]`.
+    pub name: Vec<String>,
+    /// Whether the item is `pub`. Items without a visibility, like trait
+    /// items, are public if their parent is.
+    pub public: bool,
+    /// The inputs that contained the item.
+    pub origins: Vec<Origin>,
+    /// The `cfg` on the merged item, if any.
+    pub predicate: Option<String>,
+    /// How the item was merged.
+    pub decision: Decision,
+}
+
+impl ManifestItem {
+    /// Whether the item was in all inputs.
+    pub fn is_shared(&self) -> bool {
+        self.decision == Decision::Shared
+    }
+
+    /// Whether the item was only in some inputs.
+    pub fn is_specific(&self) -> bool {
+        matches!(
+            self.decision,
+            Decision::Specific | Decision::Duplicated { .. }
+        )
+    }
+}
+
+/// How an item was merged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum Decision {
+    /// The item was in all inputs, and merged in place.
+    Shared,
+    /// The item was only in some inputs.
+    Specific,
+    /// The item differs between the inputs, and was duplicated for each
+    /// version.
+    Duplicated {
+        /// The path to the first node that differs from the other version,
+        /// starting with the item itself, e.g. `["mod sys", "fn foo"]`.
+        path: Vec<String>,
+        /// That node in this version, if any.
+        this: Option<String>,
+        /// That node in the other version, if any.
+        other: Option<String>,
+    },
+    /// The item was generated rather than merged from the inputs, like the
+    /// `compile_error!` from
+    /// [`MergeOptions::compile_error`][crate::MergeOptions::compile_error].
+    Generated,
+}
+
+impl Manifest {
+    /// The number of top-level items that are shared between all inputs.
+    pub fn shared(&self) -> usize {
+        self.top_level().filter(|item| item.is_shared()).count()
+    }
+
+    /// The number of top-level items that are only in some inputs.
+    pub fn specific(&self) -> usize {
+        self.top_level().filter(|item| item.is_specific()).count()
+    }
+
+    fn top_level(&self) -> impl Iterator<Item = &ManifestItem> {
+        self.items.iter().filter(|item| item.path.len() == 1)
+    }
+
+    /// The public items that were shared in `previous`, but aren't anymore.
+    pub fn newly_specific<'a>(&'a self, previous: &Manifest) -> Vec<&'a ManifestItem> {
+        self.items
+            .iter()
+            .filter(|item| item.public && item.is_specific())
+            .filter(|item| {
+                previous
+                    .items
+                    .iter()
+                    .any(|old| old.public && old.is_shared() && old.name == item.name)
+            })
+            .collect()
+    }
+
+    /// The manifest as pretty-printed JSON.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Read a manifest written by [`to_json`][Self::to_json].
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// Like [`merge_files_with_options`][crate::merge_files_with_options], but
+/// also return a [`Manifest`] describing how each item was merged.
+pub fn merge_files_with_manifest(
+    input: &[(File, Cfgs)],
+    options: &MergeOptions,
+) -> Result<(File, Manifest), Error> {
+    let (file, map, nodes) = merge_and_collect(input, options, true)?;
+    Ok((file, build(input.len(), &map, &nodes)))
+}
+
+/// The manifest for the merged `nodes` at the entries of `map`.
+pub(crate) fn build(inputs: usize, map: &SourceMap, nodes: &[Node]) -> Manifest {
+    let items = map
+        .items
+        .iter()
+        .zip(nodes)
+        .map(|(item, node)| {
+            let decision = if item.origins.is_empty() {
+                Decision::Generated
+            } else if item.origins.len() == inputs {
+                Decision::Shared
+            } else {
+                map.items
+                    .iter()
+                    .zip(nodes)
+                    .find(|(other, _)| is_other_version(item, other))
+                    .and_then(|(_, other)| divergence(&node.bare, &other.bare))
+                    .unwrap_or(Decision::Specific)
+            };
+            ManifestItem {
+                path: item.path.clone(),
+                name: item.name.clone(),
+                public: node.public,
+                origins: item.origins.clone(),
+                predicate: node.predicate.clone(),
+                decision,
+            }
+        })
+        .collect();
+    Manifest { inputs, items }
+}
+
+/// Whether `other` is a sibling with the same name from different inputs.
+pub(crate) fn is_other_version(item: &MappedItem, other: &MappedItem) -> bool {
+    let parent = item.path.len() - 1;
+    other.path != item.path
+        && other.path.len() == item.path.len()
+        && other.path[..parent] == item.path[..parent]
+        && other.name == item.name
+        && !other
+            .origins
+            .iter()
+            .any(|origin| item.origins.iter().any(|this| this.input == origin.input))
+}
+
+/// How `this` differs from `other`, a version of the same item, if it does.
+fn divergence(this: &AnyNode, other: &AnyNode) -> Option<Decision> {
+    fn first<T: Describe>(this: &T, other: &T, path: &mut Vec<String>) -> Option<Difference> {
+        first_difference(slice::from_ref(this), slice::from_ref(other), path)
+    }
+
+    let mut path = Vec::new();
+    let (this, other) = match (this, other) {
+        (AnyNode::Item(this), AnyNode::Item(other)) => first(this, other, &mut path),
+        (AnyNode::ImplItem(this), AnyNode::ImplItem(other)) => first(this, other, &mut path),
+        (AnyNode::TraitItem(this), AnyNode::TraitItem(other)) => first(this, other, &mut path),
+        (AnyNode::ForeignItem(this), AnyNode::ForeignItem(other)) => first(this, other, &mut path),
+        _ => None,
+    }?;
+    Some(Decision::Duplicated { path, this, other })
+}
@@ -0,0 +1,353 @@
+//! Settings for how files are merged.
+use std::any::{Any, TypeId};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ptr;
+use std::sync::Arc;
+
+use quote::ToTokens;
+use syn::{Attribute, Ident, ItemMod, Macro};
+
+use crate::source_map::Origins;
+use crate::{Cfgs, Error, Merge, Predicate};
+
+type TopLevelEqFn<T> = dyn Fn(&T, &T) -> Option<bool> + Send + Sync;
+type MergeFn<T> = dyn Fn(&[(&T, &Cfgs)]) -> Option<T> + Send + Sync;
+type AddAttrFn<T> = dyn Fn(&mut T, &Attribute) -> bool + Send + Sync;
+type ModuleTopLevelEqFn = dyn Fn(&str, &ItemMod, &ItemMod) -> Option<bool> + Send + Sync;
+
+/// Options for [`merge_files_with_options`][crate::merge_files_with_options].
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    pub(crate) universe: Predicate,
+    pub(crate) minimize: bool,
+    pub(crate) compile_error: Option<String>,
+    pub(crate) doc_cfg: bool,
+    callbacks: Callbacks,
+}
+
+/// User callbacks, keyed by the type of node they apply to.
+///
+/// The values are `Arc<TopLevelEqFn<T>>` etc. for the type of the key.
+#[derive(Clone, Default)]
+struct Callbacks {
+    top_level_eq: BTreeMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    merge: BTreeMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    add_attr: BTreeMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    macro_top_level_eq: BTreeMap<String, Arc<TopLevelEqFn<Macro>>>,
+    module_top_level_eq: Option<Arc<ModuleTopLevelEqFn>>,
+}
+
+impl fmt::Debug for Callbacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Callbacks")
+            .field("top_level_eq", &self.top_level_eq.len())
+            .field("merge", &self.merge.len())
+            .field("add_attr", &self.add_attr.len())
+            .field("macro_top_level_eq", &self.macro_top_level_eq.keys())
+            .field("module_top_level_eq", &self.module_top_level_eq.is_some())
+            .finish()
+    }
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MergeOptions {
+    /// The options used by [`merge_files`][crate::merge_files].
+    pub fn new() -> Self {
+        Self {
+            universe: Predicate::TRUE,
+            minimize: false,
+            compile_error: None,
+            doc_cfg: false,
+            callbacks: Callbacks::default(),
+        }
+    }
+
+    /// A predicate that is known to hold in every configuration the merged
+    /// file will be compiled in, e.g. `any(unix, windows)`.
+    ///
+    /// The emitted `cfg`s are simplified under it, so that parts that always
+    /// hold are left out. Defaults to `all()`.
+    pub fn universe(mut self, universe: Predicate) -> Self {
+        self.universe = universe;
+        self
+    }
+
+    /// Whether to simplify `cfg`s on nested items under the `cfg`s of their
+    /// parents, like [`simplify_nested_cfgs`][crate::simplify_nested_cfgs].
+    pub fn minimize(mut self, value: bool) -> Self {
+        self.minimize = value;
+        self
+    }
+
+    /// Emit `#[cfg(not(any(...)))] compile_error!(message)` at the end of
+    /// the file, which fails compilation in configurations that none of the
+    /// inputs were created for.
+    ///
+    /// Nothing is emitted if the inputs cover the whole universe.
+    pub fn compile_error(mut self, message: impl Into<String>) -> Self {
+        self.compile_error = Some(message.into());
+        self
+    }
+
+    /// Whether to add `#[cfg_attr(docsrs, doc(cfg(pred)))]` to public items
+    /// that are only available under `pred`, so that the documentation shows
+    /// where they are available.
+    ///
+    /// This requires the nightly `doc_cfg` feature to be enabled when
+    /// documenting with `--cfg docsrs`.
+    pub fn doc_cfg(mut self, value: bool) -> Self {
+        self.doc_cfg = value;
+        self
+    }
+
+    /// Override whether two nodes of type `T` are considered the same node,
+    /// and thus merged instead of emitted separately.
+    ///
+    /// The callback returns `None` to fall back to the default behaviour.
+    /// E.g. to never merge inside `#[repr(C)]` structs:
+    ///
+    /// ```
+    /// # use syn_merge::MergeOptions;
+    /// let options = MergeOptions::new().top_level_eq_with(|a: &syn::ItemStruct, b| {
+    ///     let repr_c = |s: &syn::ItemStruct| {
+    ///         s.attrs.iter().any(|attr| {
+    ///             attr.path().is_ident("repr")
+    ///                 && attr.parse_args::<syn::Ident>().is_ok_and(|arg| arg == "C")
+    ///         })
+    ///     };
+    ///     if repr_c(a) || repr_c(b) {
+    ///         Some(a == b)
+    ///     } else {
+    ///         None
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// Replaces any earlier callback for `T`.
+    pub fn top_level_eq_with<T: Merge>(
+        mut self,
+        f: impl Fn(&T, &T) -> Option<bool> + Send + Sync + 'static,
+    ) -> Self {
+        let f: Arc<TopLevelEqFn<T>> = Arc::new(f);
+        self.callbacks
+            .top_level_eq
+            .insert(TypeId::of::<T>(), Arc::new(f));
+        self
+    }
+
+    /// Override whether two invocations of the macro at `path` (e.g.
+    /// `"my_macro"`) are considered the same node.
+    ///
+    /// The callback returns `None` to fall back to the default behaviour,
+    /// which is to require the invocations to be equal.
+    pub fn macro_top_level_eq_with(
+        mut self,
+        path: impl Into<String>,
+        f: impl Fn(&Macro, &Macro) -> Option<bool> + Send + Sync + 'static,
+    ) -> Self {
+        self.callbacks
+            .macro_top_level_eq
+            .insert(path.into(), Arc::new(f));
+        self
+    }
+
+    /// Override whether two inline modules are considered the same node,
+    /// given their path in the merged file, like `sys::tests`.
+    ///
+    /// The callback returns `None` to fall back to the default behaviour.
+    /// Replaces any earlier callback for modules, and takes precedence over
+    /// callbacks for `syn::ItemMod`.
+    pub fn module_top_level_eq_with(
+        mut self,
+        f: impl Fn(&str, &ItemMod, &ItemMod) -> Option<bool> + Send + Sync + 'static,
+    ) -> Self {
+        self.callbacks.module_top_level_eq = Some(Arc::new(f));
+        self
+    }
+
+    /// Override how nodes of type `T` that are considered the same are
+    /// merged into one.
+    ///
+    /// The callback returns `None` to fall back to the default behaviour.
+    /// Replaces any earlier callback for `T`.
+    pub fn merge_with<T: Merge>(
+        mut self,
+        f: impl Fn(&[(&T, &Cfgs)]) -> Option<T> + Send + Sync + 'static,
+    ) -> Self {
+        let f: Arc<MergeFn<T>> = Arc::new(f);
+        self.callbacks.merge.insert(TypeId::of::<T>(), Arc::new(f));
+        self
+    }
+
+    /// Override how a `#[cfg]` attribute is placed on nodes of type `T`.
+    ///
+    /// The callback returns whether it placed the attribute, and `false` to
+    /// fall back to the default behaviour. Replaces any earlier callback for
+    /// `T`.
+    pub fn add_attr_with<T: Merge>(
+        mut self,
+        f: impl Fn(&mut T, &Attribute) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        let f: Arc<AddAttrFn<T>> = Arc::new(f);
+        self.callbacks
+            .add_attr
+            .insert(TypeId::of::<T>(), Arc::new(f));
+        self
+    }
+}
+
+/// The context that nodes are merged in.
+///
+/// Implementations of [`Merge`] should merge, compare and add attributes to
+/// their children through this, so that the callbacks in [`MergeOptions`]
+/// are used.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MergeContext<'a> {
+    callbacks: Option<&'a Callbacks>,
+    origins: Option<&'a Origins>,
+    /// The configuration of each input, used to find the index of the input
+    /// that a value came from.
+    inputs: &'a [&'a Cfgs],
+    /// The module that the nodes are in.
+    module: Option<&'a Module<'a>>,
+}
+
+/// A module that nodes are merged in, with the module it is in.
+#[derive(Debug)]
+struct Module<'a> {
+    parent: Option<&'a Module<'a>>,
+    name: &'a Ident,
+}
+
+impl<'a> MergeContext<'a> {
+    /// A context using the callbacks in `options`.
+    pub fn new(options: &'a MergeOptions) -> Self {
+        Self {
+            callbacks: Some(&options.callbacks),
+            origins: None,
+            inputs: &[],
+            module: None,
+        }
+    }
+
+    pub(crate) fn with_inputs(mut self, inputs: &'a [&'a Cfgs]) -> Self {
+        self.inputs = inputs;
+        self
+    }
+
+    /// The index of the input with the configuration `cfgs`.
+    ///
+    /// The configurations are compared by address, since different inputs
+    /// may have equal configurations.
+    pub(crate) fn input_index(&self, cfgs: &Cfgs) -> Option<usize> {
+        self.inputs.iter().position(|this| ptr::eq(*this, cfgs))
+    }
+
+    pub(crate) fn with_origins(mut self, origins: Option<&'a Origins>) -> Self {
+        self.origins = origins;
+        self
+    }
+
+    /// Where merged items are recorded, if a source map is wanted.
+    pub(crate) fn origins(&self) -> Option<&'a Origins> {
+        self.origins
+    }
+
+    /// The `::`-joined path of the module `name` within the current module,
+    /// relative to the merged file.
+    pub(crate) fn module_path(&self, name: &Ident) -> String {
+        let mut path = name.to_string();
+        let mut module = self.module;
+        while let Some(this) = module {
+            path = format!("{}::{path}", this.name);
+            module = this.parent;
+        }
+        path
+    }
+
+    fn callback<F: ?Sized + 'static>(
+        &self,
+        map: impl FnOnce(&'a Callbacks) -> &'a BTreeMap<TypeId, Arc<dyn Any + Send + Sync>>,
+        key: TypeId,
+    ) -> Option<&'a Arc<F>> {
+        map(self.callbacks?).get(&key)?.downcast_ref::<Arc<F>>()
+    }
+
+    /// Whether `a` and `b` are the same node, see [`Merge::top_level_eq`].
+    pub fn top_level_eq<T: Merge>(&self, a: &T, b: &T) -> bool {
+        if let Some(f) = self.callback::<TopLevelEqFn<T>>(|c| &c.top_level_eq, TypeId::of::<T>()) {
+            if let Some(res) = f(a, b) {
+                return res;
+            }
+        }
+        if let (Some(callbacks), Some(a), Some(b)) = (
+            self.callbacks,
+            (a as &dyn Any).downcast_ref::<Macro>(),
+            (b as &dyn Any).downcast_ref::<Macro>(),
+        ) {
+            let path = a.path.to_token_stream().to_string().replace(' ', "");
+            if let Some(f) = callbacks.macro_top_level_eq.get(&path) {
+                if let Some(res) = f(a, b) {
+                    return res;
+                }
+            }
+        }
+        if let (Some(f), Some(a), Some(b)) = (
+            self.callbacks.and_then(|c| c.module_top_level_eq.as_ref()),
+            (a as &dyn Any).downcast_ref::<ItemMod>(),
+            (b as &dyn Any).downcast_ref::<ItemMod>(),
+        ) {
+            if let Some(res) = f(&self.module_path(&a.ident), a, b) {
+                return res;
+            }
+        }
+        a.top_level_eq(b, self)
+    }
+
+    /// Merge the nodes into one, see [`Merge::merge`].
+    pub fn merge<'b, T: Merge, I: IntoIterator<Item = (&'b T, &'b Cfgs)>>(
+        &self,
+        iter: I,
+    ) -> Result<T, Error>
+    where
+        I::IntoIter: Clone,
+    {
+        let iter = iter.into_iter();
+        if let Some(f) = self.callback::<MergeFn<T>>(|c| &c.merge, TypeId::of::<T>()) {
+            let values: Vec<_> = iter.clone().collect();
+            if let Some(res) = f(&values) {
+                return Ok(res);
+            }
+        }
+        // Keep track of the modules that the children are merged in
+        let first = iter.clone().next().map(|(value, _)| value as &dyn Any);
+        if let Some(module) = first.and_then(|value| value.downcast_ref::<ItemMod>()) {
+            let module = Module {
+                parent: self.module,
+                name: &module.ident,
+            };
+            let cx = MergeContext {
+                module: Some(&module),
+                ..*self
+            };
+            return T::merge(iter, &cx);
+        }
+        T::merge(iter, self)
+    }
+
+    /// Add a `#[cfg]` attribute to the node, see [`Merge::add_attr`].
+    pub fn add_attr<T: Merge>(&self, node: &mut T, attr: Attribute) -> Result<(), Error> {
+        if let Some(f) = self.callback::<AddAttrFn<T>>(|c| &c.add_attr, TypeId::of::<T>()) {
+            if f(node, &attr) {
+                return Ok(());
+            }
+        }
+        node.add_attr(attr, self)
+    }
+}
@@ -0,0 +1,314 @@
+//! Find definitions that are active more than once in the same configuration.
+use std::fmt;
+
+use syn::{Fields, File, ForeignItem, Ident, ImplItem, Item, TraitItem};
+
+use crate::verify::Describe;
+use crate::{Cfgs, Merge, Predicate};
+
+/// The namespace that a definition lives in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Namespace {
+    /// Types, traits and modules.
+    Type,
+    /// Functions, constants, statics and tuple or unit struct constructors.
+    Value,
+    /// Trait implementations, named like `impl Trait for Type`.
+    Impl,
+}
+
+/// Two definitions of the same name that are active at the same time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlap {
+    /// The scope that the definitions are in, e.g. `["mod sys", "impl Foo"]`.
+    ///
+    /// Empty for the top level of the file.
+    pub scope: Vec<String>,
+    /// The namespace of the definitions.
+    pub namespace: Namespace,
+    /// The name of the definitions.
+    pub name: String,
+    /// The indices of the two definitions within their scope.
+    pub items: (usize, usize),
+    /// The indices of the configurations in which both are active.
+    pub configs: Vec<usize>,
+}
+
+impl fmt::Display for Overlap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let namespace = match self.namespace {
+            Namespace::Type => "type",
+            Namespace::Value => "value",
+            Namespace::Impl => "impl",
+        };
+        write!(
+            f,
+            "`{}` is defined twice in the {namespace} namespace",
+            self.name
+        )?;
+        if !self.scope.is_empty() {
+            write!(f, " of `{}`", self.scope.join(" > "))?;
+        }
+        write!(f, " (items {} and {})", self.items.0, self.items.1)?;
+        write!(f, " in configurations {:?}", self.configs)
+    }
+}
+
+/// Check that no two definitions of the same name in the same namespace and
+/// scope are active at once under any of `configs`.
+///
+/// Scopes are the file itself, inline modules, impls and traits. Items in
+/// `extern` blocks belong to the enclosing scope. The inherent impls of a
+/// type within the same scope share a single scope, named after the first
+/// of them, and the indices of their items count through all of them. Trait
+/// impls are definitions themselves, so implementing a trait twice for the
+/// same type is also reported.
+///
+/// Types and traits are compared syntactically, e.g. `impl Foo` and
+/// `impl self::Foo` are different types.
+///
+/// Items whose `#[cfg]` can't be parsed are skipped, as are `use` items and
+/// macros. Defining a `macro_rules!` macro again is allowed, and shadows the
+/// earlier definition.
+pub fn check_overlaps(merged: &File, configs: &[Cfgs]) -> Vec<Overlap> {
+    let mut checker = Checker {
+        configs,
+        scope: Vec::new(),
+        overlaps: Vec::new(),
+    };
+    checker.check_items(&merged.items, &vec![true; configs.len()]);
+    checker.overlaps
+}
+
+/// The names that an item defines in its scope.
+pub(crate) fn item_names(item: &Item) -> Vec<(Namespace, &Ident)> {
+    match item {
+        Item::Const(item) => vec![(Namespace::Value, &item.ident)],
+        Item::Enum(item) => vec![(Namespace::Type, &item.ident)],
+        Item::ExternCrate(item) => match &item.rename {
+            Some((_, rename)) => vec![(Namespace::Type, rename)],
+            None => vec![(Namespace::Type, &item.ident)],
+        },
+        Item::Fn(item) => vec![(Namespace::Value, &item.sig.ident)],
+        Item::Mod(item) => vec![(Namespace::Type, &item.ident)],
+        Item::Static(item) => vec![(Namespace::Value, &item.ident)],
+        Item::Struct(item) if matches!(item.fields, Fields::Named(_)) => {
+            vec![(Namespace::Type, &item.ident)]
+        }
+        Item::Struct(item) => vec![
+            (Namespace::Type, &item.ident),
+            (Namespace::Value, &item.ident),
+        ],
+        Item::Trait(item) => vec![(Namespace::Type, &item.ident)],
+        Item::TraitAlias(item) => vec![(Namespace::Type, &item.ident)],
+        Item::Type(item) => vec![(Namespace::Type, &item.ident)],
+        Item::Union(item) => vec![(Namespace::Type, &item.ident)],
+        _ => vec![],
+    }
+}
+
+/// The items of an impl, and the configurations that it is active in.
+type ImplBlock<'a> = (&'a [ImplItem], Vec<bool>);
+
+struct Definition {
+    namespace: Namespace,
+    name: String,
+    item: usize,
+    active: Vec<bool>,
+}
+
+struct Checker<'a> {
+    configs: &'a [Cfgs],
+    scope: Vec<String>,
+    overlaps: Vec<Overlap>,
+}
+
+impl Checker<'_> {
+    /// In which configurations the node is active, given that its parent is
+    /// active in `parent`.
+    ///
+    /// Returns `None` if the node's `cfg` can't be parsed.
+    fn active<T: Merge>(&self, node: &T, parent: &[bool]) -> Option<Vec<bool>> {
+        let predicate = match Predicate::from_attributes(node.attrs()) {
+            Some(Ok(predicate)) => predicate,
+            Some(Err(_)) => return None,
+            None => Predicate::TRUE,
+        };
+        Some(
+            self.configs
+                .iter()
+                .zip(parent)
+                .map(|(cfgs, active)| *active && predicate.eval(cfgs))
+                .collect(),
+        )
+    }
+
+    fn check_items(&mut self, items: &[Item], parent: &[bool]) {
+        let mut definitions = Vec::new();
+        for (idx, item) in items.iter().enumerate() {
+            let Some(active) = self.active(item, parent) else {
+                continue;
+            };
+            if let Item::ForeignMod(foreign) = item {
+                for foreign_item in &foreign.items {
+                    let Some(active) = self.active(foreign_item, &active) else {
+                        continue;
+                    };
+                    let (namespace, name) = match foreign_item {
+                        ForeignItem::Fn(item) => (Namespace::Value, &item.sig.ident),
+                        ForeignItem::Static(item) => (Namespace::Value, &item.ident),
+                        ForeignItem::Type(item) => (Namespace::Type, &item.ident),
+                        _ => continue,
+                    };
+                    definitions.push(Definition {
+                        namespace,
+                        name: name.to_string(),
+                        item: idx,
+                        active,
+                    });
+                }
+                continue;
+            }
+            if let Item::Impl(impl_) = item {
+                if impl_.trait_.is_some() {
+                    definitions.push(Definition {
+                        namespace: Namespace::Impl,
+                        name: item.describe(),
+                        item: idx,
+                        active: active.clone(),
+                    });
+                }
+            }
+            for (namespace, name) in item_names(item) {
+                definitions.push(Definition {
+                    namespace,
+                    name: name.to_string(),
+                    item: idx,
+                    active: active.clone(),
+                });
+            }
+        }
+        self.report(definitions);
+
+        // The items of the inherent impls of each type, in order of the
+        // first impl of the type
+        let mut inherent: Vec<(String, Vec<ImplBlock<'_>>)> = Vec::new();
+        for item in items {
+            let Some(active) = self.active(item, parent) else {
+                continue;
+            };
+            match item {
+                Item::Impl(impl_) if impl_.trait_.is_none() => {
+                    let scope = item.describe();
+                    let blocks = match inherent.iter_mut().find(|(other, _)| *other == scope) {
+                        Some((_, blocks)) => blocks,
+                        None => {
+                            inherent.push((scope, Vec::new()));
+                            &mut inherent.last_mut().unwrap().1
+                        }
+                    };
+                    blocks.push((&impl_.items, active));
+                }
+                Item::Mod(mod_) => {
+                    if let Some((_, items)) = &mod_.content {
+                        self.scope.push(item.describe());
+                        self.check_items(items, &active);
+                        self.scope.pop();
+                    }
+                }
+                Item::Impl(impl_) => {
+                    self.scope.push(item.describe());
+                    self.check_impl_items(&[(&impl_.items, active)]);
+                    self.scope.pop();
+                }
+                Item::Trait(trait_) => {
+                    self.scope.push(item.describe());
+                    self.check_trait_items(&trait_.items, &active);
+                    self.scope.pop();
+                }
+                _ => {}
+            }
+        }
+        for (scope, blocks) in inherent {
+            self.scope.push(scope);
+            self.check_impl_items(&blocks);
+            self.scope.pop();
+        }
+    }
+
+    /// Check the items of one or more impl blocks, each active in the given
+    /// configurations.
+    fn check_impl_items(&mut self, blocks: &[ImplBlock<'_>]) {
+        let mut definitions = Vec::new();
+        let items = blocks
+            .iter()
+            .flat_map(|(items, parent)| items.iter().map(move |item| (item, parent)));
+        for (idx, (item, parent)) in items.enumerate() {
+            let Some(active) = self.active(item, parent) else {
+                continue;
+            };
+            let (namespace, name) = match item {
+                ImplItem::Const(item) => (Namespace::Value, &item.ident),
+                ImplItem::Fn(item) => (Namespace::Value, &item.sig.ident),
+                ImplItem::Type(item) => (Namespace::Type, &item.ident),
+                _ => continue,
+            };
+            definitions.push(Definition {
+                namespace,
+                name: name.to_string(),
+                item: idx,
+                active,
+            });
+        }
+        self.report(definitions);
+    }
+
+    fn check_trait_items(&mut self, items: &[TraitItem], parent: &[bool]) {
+        let mut definitions = Vec::new();
+        for (idx, item) in items.iter().enumerate() {
+            let Some(active) = self.active(item, parent) else {
+                continue;
+            };
+            let (namespace, name) = match item {
+                TraitItem::Const(item) => (Namespace::Value, &item.ident),
+                TraitItem::Fn(item) => (Namespace::Value, &item.sig.ident),
+                TraitItem::Type(item) => (Namespace::Type, &item.ident),
+                _ => continue,
+            };
+            definitions.push(Definition {
+                namespace,
+                name: name.to_string(),
+                item: idx,
+                active,
+            });
+        }
+        self.report(definitions);
+    }
+
+    fn report(&mut self, definitions: Vec<Definition>) {
+        for (i, first) in definitions.iter().enumerate() {
+            for second in &definitions[i + 1..] {
+                if first.namespace != second.namespace || first.name != second.name {
+                    continue;
+                }
+                let configs: Vec<usize> = first
+                    .active
+                    .iter()
+                    .zip(&second.active)
+                    .enumerate()
+                    .filter(|(_, (a, b))| **a && **b)
+                    .map(|(idx, _)| idx)
+                    .collect();
+                if !configs.is_empty() {
+                    self.overlaps.push(Overlap {
+                        scope: self.scope.clone(),
+                        namespace: first.namespace,
+                        name: first.name.clone(),
+                        items: (first.item, second.item),
+                        configs,
+                    });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,403 @@
+//! A model of `cfg` predicates that we can reason about.
+use std::collections::BTreeSet;
+use std::fmt;
+
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{parse_quote, Attribute, Expr, ExprLit, Lit, Meta, Token};
+
+use crate::Cfgs;
+
+/// Keys that can only ever have a single value at a time.
+///
+/// `target_family` and `target_feature` are deliberately not in here.
+const SINGLE_VALUED_KEYS: &[&str] = &[
+    "panic",
+    "target_abi",
+    "target_arch",
+    "target_endian",
+    "target_env",
+    "target_os",
+    "target_pointer_width",
+    "target_vendor",
+];
+
+/// More atoms than this and we stop trying to use truth tables.
+const MAX_ATOMS: usize = 12;
+
+/// A `cfg` predicate, e.g. the `all(unix, target_os = "linux")` in
+/// `#[cfg(all(unix, target_os = "linux"))]`.
+///
+/// `all()` is always true, and `any()` is always false.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Predicate {
+    /// A name, e.g. `unix`.
+    Name(String),
+    /// A key-value pair, e.g. `target_os = "linux"`.
+    KeyValue(String, String),
+    /// `all(...)`.
+    All(Vec<Predicate>),
+    /// `any(...)`.
+    Any(Vec<Predicate>),
+    /// `not(...)`.
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    /// The predicate that is always true, `all()`.
+    pub const TRUE: Self = Self::All(Vec::new());
+
+    /// The predicate that is always false, `any()`.
+    pub const FALSE: Self = Self::Any(Vec::new());
+
+    /// Parse the predicate from a `#[cfg(...)]` attribute.
+    ///
+    /// Returns `None` if the attribute is not a `cfg` attribute.
+    pub fn from_attribute(attr: &Attribute) -> Option<syn::Result<Self>> {
+        if attr.path().is_ident("cfg") {
+            Some(attr.parse_args())
+        } else {
+            None
+        }
+    }
+
+    /// Parse the predicate and the attributes from a
+    /// `#[cfg_attr(predicate, attrs...)]` attribute.
+    ///
+    /// Returns `None` if the attribute is not a `cfg_attr` attribute.
+    pub(crate) fn from_cfg_attr(
+        attr: &Attribute,
+    ) -> Option<syn::Result<(Self, Punctuated<Meta, Token![,]>)>> {
+        if !attr.path().is_ident("cfg_attr") {
+            return None;
+        }
+        Some(attr.parse_args_with(|input: ParseStream<'_>| {
+            let predicate: Self = input.parse()?;
+            input.parse::<Token![,]>()?;
+            let metas = Punctuated::parse_terminated(input)?;
+            Ok((predicate, metas))
+        }))
+    }
+
+    /// The combined predicate of all the `#[cfg(...)]` attributes in `attrs`.
+    ///
+    /// Returns `None` if there are no `cfg` attributes.
+    pub fn from_attributes(attrs: &[Attribute]) -> Option<syn::Result<Self>> {
+        let mut preds = Vec::new();
+        for attr in attrs {
+            match Self::from_attribute(attr) {
+                Some(Ok(pred)) => preds.push(pred),
+                Some(Err(err)) => return Some(Err(err)),
+                None => {}
+            }
+        }
+        match preds.len() {
+            0 => None,
+            1 => preds.pop().map(Ok),
+            _ => Some(Ok(Self::All(preds))),
+        }
+    }
+
+    /// The `#[cfg(...)]` attribute for this predicate.
+    pub fn attribute(&self) -> Attribute {
+        parse_quote! {
+            #[cfg(#self)]
+        }
+    }
+
+    pub fn is_true(&self) -> bool {
+        matches!(self, Self::All(preds) if preds.is_empty())
+    }
+
+    pub fn is_false(&self) -> bool {
+        matches!(self, Self::Any(preds) if preds.is_empty())
+    }
+
+    /// Combine two predicates with `all`.
+    pub fn and(self, other: Self) -> Self {
+        Self::All(vec![self, other]).simplify()
+    }
+
+    /// Combine two predicates with `any`.
+    pub fn or(self, other: Self) -> Self {
+        Self::Any(vec![self, other]).simplify()
+    }
+
+    /// The negation of this predicate.
+    pub fn negate(self) -> Self {
+        match self {
+            Self::Not(pred) => *pred,
+            pred if pred.is_true() => Self::FALSE,
+            pred if pred.is_false() => Self::TRUE,
+            pred => Self::Not(Box::new(pred)),
+        }
+    }
+
+    /// Simplify the predicate, without changing its meaning.
+    ///
+    /// This flattens nested `all` and `any`, removes duplicates and constant
+    /// parts, and unwraps single-element `all` and `any`.
+    pub fn simplify(&self) -> Self {
+        self.simplify_under(&Self::TRUE)
+    }
+
+    /// Simplify the predicate in a context where `context` is known to hold.
+    ///
+    /// E.g. `all(unix, target_os = "linux")` becomes `target_os = "linux"` in
+    /// a context where `unix` holds, and predicates that are always true in
+    /// the context become `all()`.
+    pub fn simplify_under(&self, context: &Self) -> Self {
+        if !context.is_true() {
+            if context.implies(self) {
+                return Self::TRUE;
+            }
+            if context.implies(&self.clone().negate()) {
+                return Self::FALSE;
+            }
+        }
+        match self {
+            Self::Name(_) | Self::KeyValue(_, _) => self.clone(),
+            Self::Not(pred) => pred.simplify_under(context).negate(),
+            Self::All(preds) => {
+                let mut res: Vec<Self> = Vec::new();
+                for pred in preds {
+                    match pred.simplify_under(context) {
+                        pred if pred.is_false() => return Self::FALSE,
+                        Self::All(inner) => res.extend(inner),
+                        pred => res.push(pred),
+                    }
+                }
+                dedup(&mut res);
+                if res.len() == 1 {
+                    res.pop().unwrap()
+                } else {
+                    Self::All(res)
+                }
+            }
+            Self::Any(preds) => {
+                let mut res: Vec<Self> = Vec::new();
+                for pred in preds {
+                    match pred.simplify_under(context) {
+                        pred if pred.is_true() => return Self::TRUE,
+                        Self::Any(inner) => res.extend(inner),
+                        pred => res.push(pred),
+                    }
+                }
+                dedup(&mut res);
+                if res.len() == 1 {
+                    res.pop().unwrap()
+                } else {
+                    Self::Any(res)
+                }
+            }
+        }
+    }
+
+    /// Whether `other` is true whenever `self` is.
+    ///
+    /// Values of keys like `target_os` are taken to be mutually exclusive.
+    ///
+    /// This is conservative, it may return `false` for very large predicates
+    /// even though the implication holds.
+    pub fn implies(&self, other: &Self) -> bool {
+        let mut atoms = BTreeSet::new();
+        self.collect_atoms(&mut atoms);
+        other.collect_atoms(&mut atoms);
+        if atoms.len() > MAX_ATOMS {
+            return self.conjuncts().contains(&other);
+        }
+        let atoms: Vec<_> = atoms.into_iter().collect();
+        (0..1u32 << atoms.len()).all(|assignment| {
+            let holds = |atom: &Self| {
+                let idx = atoms.iter().position(|a| *a == atom).unwrap();
+                assignment & (1 << idx) != 0
+            };
+            if !is_consistent(&atoms, assignment) {
+                return true;
+            }
+            !self.eval_with(&holds) || other.eval_with(&holds)
+        })
+    }
+
+    /// Evaluate the predicate in the given configuration.
+    pub fn eval(&self, cfgs: &Cfgs) -> bool {
+        self.eval_with(&|atom| cfgs.holds(atom))
+    }
+
+    /// Replace the names and key-value pairs whose value is known by `all()`
+    /// or `any()`, and simplify the result.
+    ///
+    /// The result is `all()` or `any()` if the predicate is decided by the
+    /// known values, and otherwise only contains unknown atoms.
+    pub(crate) fn partial_eval(&self, value: &impl Fn(&Self) -> Option<bool>) -> Self {
+        fn substitute(pred: &Predicate, value: &impl Fn(&Predicate) -> Option<bool>) -> Predicate {
+            match pred {
+                Predicate::Name(_) | Predicate::KeyValue(_, _) => match value(pred) {
+                    Some(true) => Predicate::TRUE,
+                    Some(false) => Predicate::FALSE,
+                    None => pred.clone(),
+                },
+                Predicate::All(preds) => {
+                    Predicate::All(preds.iter().map(|pred| substitute(pred, value)).collect())
+                }
+                Predicate::Any(preds) => {
+                    Predicate::Any(preds.iter().map(|pred| substitute(pred, value)).collect())
+                }
+                Predicate::Not(pred) => substitute(pred, value).negate(),
+            }
+        }
+        substitute(self, value).simplify()
+    }
+
+    /// Evaluate the predicate, given a way to evaluate names and key-value
+    /// pairs.
+    pub(crate) fn eval_with(&self, holds: &impl Fn(&Self) -> bool) -> bool {
+        match self {
+            Self::Name(_) | Self::KeyValue(_, _) => holds(self),
+            Self::All(preds) => preds.iter().all(|pred| pred.eval_with(holds)),
+            Self::Any(preds) => preds.iter().any(|pred| pred.eval_with(holds)),
+            Self::Not(pred) => !pred.eval_with(holds),
+        }
+    }
+
+    pub(crate) fn collect_atoms<'a>(&'a self, atoms: &mut BTreeSet<&'a Self>) {
+        match self {
+            Self::Name(_) | Self::KeyValue(_, _) => {
+                atoms.insert(self);
+            }
+            Self::All(preds) | Self::Any(preds) => {
+                for pred in preds {
+                    pred.collect_atoms(atoms);
+                }
+            }
+            Self::Not(pred) => pred.collect_atoms(atoms),
+        }
+    }
+
+    fn conjuncts(&self) -> Vec<&Self> {
+        match self {
+            Self::All(preds) => preds.iter().flat_map(Self::conjuncts).collect(),
+            pred => vec![pred],
+        }
+    }
+}
+
+fn dedup(preds: &mut Vec<Predicate>) {
+    let mut seen = BTreeSet::new();
+    preds.retain(|pred| seen.insert(pred.clone()));
+}
+
+/// Whether the assignment sets at most one value for each single-valued key.
+fn is_consistent(atoms: &[&Predicate], assignment: u32) -> bool {
+    let mut keys = BTreeSet::new();
+    atoms.iter().enumerate().all(|(idx, atom)| match atom {
+        Predicate::KeyValue(key, _)
+            if assignment & (1 << idx) != 0 && SINGLE_VALUED_KEYS.contains(&&**key) =>
+        {
+            keys.insert(key)
+        }
+        _ => true,
+    })
+}
+
+impl Parse for Predicate {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let meta: Meta = input.parse()?;
+        Self::from_meta(&meta)
+    }
+}
+
+impl Predicate {
+    fn from_meta(meta: &Meta) -> syn::Result<Self> {
+        match meta {
+            Meta::Path(path) => match path.get_ident() {
+                Some(ident) => Ok(Self::Name(ident.to_string())),
+                None => Err(syn::Error::new_spanned(path, "expected a cfg name")),
+            },
+            Meta::NameValue(name_value) => {
+                let Some(ident) = name_value.path.get_ident() else {
+                    return Err(syn::Error::new_spanned(
+                        &name_value.path,
+                        "expected a cfg name",
+                    ));
+                };
+                match &name_value.value {
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Str(value),
+                        ..
+                    }) => Ok(Self::KeyValue(ident.to_string(), value.value())),
+                    value => Err(syn::Error::new_spanned(value, "expected a string literal")),
+                }
+            }
+            Meta::List(list) => {
+                let args = list.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
+                let mut args = args
+                    .iter()
+                    .map(Self::from_meta)
+                    .collect::<syn::Result<Vec<_>>>()?;
+                if list.path.is_ident("all") {
+                    Ok(Self::All(args))
+                } else if list.path.is_ident("any") {
+                    Ok(Self::Any(args))
+                } else if list.path.is_ident("not") && args.len() == 1 {
+                    Ok(Self::Not(Box::new(args.pop().unwrap())))
+                } else if list.path.is_ident("not") {
+                    Err(syn::Error::new_spanned(
+                        list,
+                        "expected exactly one argument",
+                    ))
+                } else {
+                    Err(syn::Error::new_spanned(
+                        &list.path,
+                        "expected `all`, `any` or `not`",
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl ToTokens for Predicate {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        tokens.extend(match self {
+            Self::Name(name) => {
+                let name = syn::Ident::new(name, proc_macro2::Span::call_site());
+                quote!(#name)
+            }
+            Self::KeyValue(key, value) => {
+                let key = syn::Ident::new(key, proc_macro2::Span::call_site());
+                quote!(#key = #value)
+            }
+            Self::All(preds) => quote!(all(#(#preds),*)),
+            Self::Any(preds) => quote!(any(#(#preds),*)),
+            Self::Not(pred) => quote!(not(#pred)),
+        });
+    }
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "{name}"),
+            Self::KeyValue(key, value) => write!(f, "{key} = {value:?}"),
+            Self::All(preds) | Self::Any(preds) => {
+                let name = if let Self::All(_) = self {
+                    "all"
+                } else {
+                    "any"
+                };
+                write!(f, "{name}(")?;
+                for (i, pred) in preds.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{pred}")?;
+                }
+                write!(f, ")")
+            }
+            Self::Not(pred) => write!(f, "not({pred})"),
+        }
+    }
+}
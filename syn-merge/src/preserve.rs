@@ -0,0 +1,480 @@
+//! Print merged files while keeping the source text of one of the inputs.
+use std::ops::Range;
+use std::path::Path;
+
+use proc_macro2::{LineColumn, Span};
+use quote::ToTokens;
+use syn::spanned::Spanned;
+use syn::{parse_quote, Attribute, File, ImplItem, Item, TraitItem};
+
+use crate::{
+    merge_files_with_options, parse_sources, Cfgs, Diagnostic, Error, ErrorKind, Merge,
+    MergeOptions,
+};
+
+/// Merge the source code of each input like [`merge_sources`], and print the
+/// result while preserving the formatting of the first input.
+///
+/// Items that are unchanged from the first input keep their original text,
+/// including comments and whitespace. Items that only gained `#[cfg]`s get
+/// those inserted after their existing attributes, and inline modules,
+/// impls and traits are preserved in the same way item by item. Everything
+/// else is printed with `prettyplease`.
+///
+/// Requires the `pretty` feature.
+///
+/// [`merge_sources`]: crate::merge_sources
+pub fn merge_sources_preserving<P: AsRef<Path>>(
+    input: &[(P, &str, Cfgs)],
+) -> Result<String, Diagnostic> {
+    merge_sources_preserving_with_options(input, &MergeOptions::new())
+}
+
+/// Like [`merge_sources_preserving`], but with the given options.
+pub fn merge_sources_preserving_with_options<P: AsRef<Path>>(
+    input: &[(P, &str, Cfgs)],
+    options: &MergeOptions,
+) -> Result<String, Diagnostic> {
+    let files = parse_sources(input)?;
+    let merged =
+        merge_files_with_options(&files, options).map_err(|err| Diagnostic::merge(&err, input))?;
+    let (_, source, _) = &input[0];
+    let (first, _) = &files[0];
+    let mut printer = Printer::new(source);
+    printer.file(&merged, first);
+    Ok(printer.out)
+}
+
+/// An edit to the source text of an input, see [`merge_sources_as_edits`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TextEdit {
+    /// The byte range to replace, which is empty for insertions.
+    pub range: Range<usize>,
+    /// The text to replace it with, which is empty for deletions.
+    pub text: String,
+}
+
+/// Merge the source code of each input like [`merge_sources`], and return
+/// the result as edits to the source of input `base`.
+///
+/// The edits are sorted and don't overlap. Applying them with
+/// [`apply_edits`] gives the merged source, with the formatting of `base`
+/// preserved like [`merge_sources_preserving`] does for the first input.
+///
+/// Requires the `pretty` feature.
+///
+/// [`merge_sources`]: crate::merge_sources
+pub fn merge_sources_as_edits<P: AsRef<Path>>(
+    input: &[(P, &str, Cfgs)],
+    base: usize,
+) -> Result<Vec<TextEdit>, Diagnostic> {
+    merge_sources_as_edits_with_options(input, base, &MergeOptions::new())
+}
+
+/// Like [`merge_sources_as_edits`], but with the given options.
+///
+/// Fails with [`ErrorKind::NoInput`] if there is no input `base`.
+pub fn merge_sources_as_edits_with_options<P: AsRef<Path>>(
+    input: &[(P, &str, Cfgs)],
+    base: usize,
+    options: &MergeOptions,
+) -> Result<Vec<TextEdit>, Diagnostic> {
+    if base >= input.len() {
+        let err = Error::new(ErrorKind::NoInput, format!("there is no input {base}"));
+        return Err(Diagnostic::merge(&err, input));
+    }
+    let files = parse_sources(input)?;
+    let merged =
+        merge_files_with_options(&files, options).map_err(|err| Diagnostic::merge(&err, input))?;
+    let (_, source, _) = &input[base];
+    let (base, _) = &files[base];
+    let mut printer = Printer::new(source);
+    printer.file(&merged, base);
+    Ok(printer.edits())
+}
+
+/// Apply edits from [`merge_sources_as_edits`] to the source they were made
+/// for.
+pub fn apply_edits(source: &str, edits: &[TextEdit]) -> String {
+    let mut res = String::with_capacity(source.len());
+    let mut cursor = 0;
+    for edit in edits {
+        res.push_str(&source[cursor..edit.range.start]);
+        res.push_str(&edit.text);
+        cursor = edit.range.end;
+    }
+    res.push_str(&source[cursor..]);
+    res
+}
+
+/// A part of the output.
+enum Segment {
+    /// Text copied from the source.
+    Copy(Range<usize>),
+    /// New text.
+    Insert(String),
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+    out: String,
+    segments: Vec<Segment>,
+}
+
+impl<'a> Printer<'a> {
+    fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        Self {
+            source,
+            line_starts,
+            out: String::new(),
+            segments: Vec::new(),
+        }
+    }
+
+    fn copy(&mut self, range: Range<usize>) {
+        self.out.push_str(&self.source[range.clone()]);
+        self.segments.push(Segment::Copy(range));
+    }
+
+    fn insert(&mut self, text: &str) {
+        self.out.push_str(text);
+        match self.segments.last_mut() {
+            Some(Segment::Insert(last)) => last.push_str(text),
+            _ => self.segments.push(Segment::Insert(text.to_string())),
+        }
+    }
+
+    /// The edits that turn the source into the output.
+    fn edits(&self) -> Vec<TextEdit> {
+        let mut edits = Vec::new();
+        let mut cursor = 0;
+        let mut pending = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Copy(range) => {
+                    if range.start != cursor || !pending.is_empty() {
+                        edits.push(TextEdit {
+                            range: cursor..range.start,
+                            text: std::mem::take(&mut pending),
+                        });
+                    }
+                    cursor = range.end;
+                }
+                Segment::Insert(text) => pending.push_str(text),
+            }
+        }
+        if cursor != self.source.len() || !pending.is_empty() {
+            edits.push(TextEdit {
+                range: cursor..self.source.len(),
+                text: pending,
+            });
+        }
+        edits
+    }
+
+    fn offset(&self, pos: LineColumn) -> usize {
+        let start = self.line_starts[pos.line - 1];
+        self.source[start..]
+            .char_indices()
+            .nth(pos.column)
+            .map_or(self.source.len(), |(idx, _)| start + idx)
+    }
+
+    fn range(&self, span: Span) -> Range<usize> {
+        self.offset(span.start())..self.offset(span.end())
+    }
+
+    /// The leading whitespace of the line containing `offset`.
+    fn indent(&self, offset: usize) -> &'a str {
+        let start = self.source[..offset].rfind('\n').map_or(0, |idx| idx + 1);
+        let line = &self.source[start..];
+        &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+    }
+
+    fn ensure_newline(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.insert("\n");
+        }
+    }
+
+    fn file(&mut self, merged: &File, base: &File) {
+        let start = match base.items.first() {
+            Some(item) => self.range(item.span()).start,
+            None => self.source.len(),
+        };
+        self.copy(0..start);
+        self.list(&merged.items, &base.items, start..self.source.len(), "", "");
+        self.ensure_newline();
+    }
+
+    /// Print the merged list of nodes, given the original list in the base
+    /// input, which spans `region` of the source.
+    fn list<T: Preserve>(
+        &mut self,
+        merged: &[T],
+        base: &[T],
+        region: Range<usize>,
+        indent: &str,
+        end_indent: &str,
+    ) {
+        let ranges: Vec<_> = base.iter().map(|node| self.range(node.span())).collect();
+        let mut cursor = 0;
+        // How far the source of the region has been copied.
+        let mut copied = region.start;
+        // Whether the last thing printed was an original node.
+        let mut after_original = false;
+        let mut printed = false;
+        for node in merged {
+            let found = base[cursor..]
+                .iter()
+                .position(|orig| T::matches(node, orig))
+                .map(|idx| cursor + idx);
+            let Some(idx) = found else {
+                // Reuse the line break after the previous node
+                if after_original && self.source[copied..region.end].starts_with('\n') {
+                    self.copy(copied..copied + 1);
+                    copied += 1;
+                }
+                self.ensure_newline();
+                if printed {
+                    self.insert("\n");
+                }
+                for line in node.unparse().lines() {
+                    if !line.is_empty() {
+                        self.insert(indent);
+                    }
+                    self.insert(line);
+                    self.insert("\n");
+                }
+                after_original = false;
+                printed = true;
+                continue;
+            };
+            let gap_start = match idx {
+                0 => region.start,
+                idx => ranges[idx - 1].end,
+            }
+            .max(copied);
+            let gap = &self.source[gap_start..ranges[idx].start];
+            // Avoid a blank line between a generated node and the braces
+            if !after_original
+                && self.out.ends_with('\n')
+                && gap.starts_with('\n')
+                && !self.source[..gap_start].ends_with('\n')
+            {
+                self.copy(gap_start + 1..ranges[idx].start);
+            } else {
+                self.copy(gap_start..ranges[idx].start);
+            }
+            T::print(self, node, &base[idx], ranges[idx].clone());
+            cursor = idx + 1;
+            copied = ranges[idx].end;
+            after_original = true;
+            printed = true;
+        }
+        if !base.is_empty() && cursor == base.len() {
+            self.copy(copied..region.end);
+        } else {
+            self.ensure_newline();
+            self.insert(end_indent);
+        }
+    }
+
+    /// Print the original attributes of a node, followed by the attributes
+    /// that were added by merging.
+    ///
+    /// Returns the offset in the source where the node's own attributes end.
+    fn attrs<T: Preserve>(&mut self, merged: &T, base: &T, range: &Range<usize>) -> usize {
+        let after_attrs = if base.attrs().is_empty() {
+            range.start
+        } else {
+            let mut stripped = base.clone();
+            if let Some(attrs) = stripped.attrs_mut() {
+                attrs.clear();
+            }
+            self.range(stripped.span()).start
+        };
+        self.copy(range.start..after_attrs);
+        let indent = self.indent(range.start);
+        for attr in &merged.attrs()[base.attrs().len()..] {
+            self.insert(&unparse_attr(attr));
+            self.insert("\n");
+            self.insert(indent);
+        }
+        after_attrs
+    }
+
+    /// Print the original text of a node, with the added attributes.
+    fn node<T: Preserve>(&mut self, merged: &T, base: &T, range: Range<usize>) {
+        let start = self.attrs(merged, base, &range);
+        self.copy(start..range.end);
+    }
+}
+
+/// Print an attribute the way `prettyplease` would.
+fn unparse_attr(attr: &Attribute) -> String {
+    let file: File = parse_quote! {
+        #attr
+        struct S;
+    };
+    let text = prettyplease::unparse(&file);
+    text.trim_end()
+        .trim_end_matches("struct S;")
+        .trim_end()
+        .to_string()
+}
+
+/// Whether `merged` is `base` with some attributes added at the end.
+fn only_attrs_added<T: Merge + PartialEq>(merged: &T, base: &T) -> bool {
+    let own = base.attrs().len();
+    if merged.attrs().get(..own) != Some(base.attrs()) {
+        return false;
+    }
+    let mut merged = merged.clone();
+    if let Some(attrs) = merged.attrs_mut() {
+        attrs.truncate(own);
+    }
+    merged == *base
+}
+
+trait Preserve: Merge + ToTokens + PartialEq {
+    /// Print the node on its own with `prettyplease`.
+    fn unparse(&self) -> String;
+
+    /// Whether `merged` can be printed using the text of `base`.
+    fn matches(merged: &Self, base: &Self) -> bool {
+        only_attrs_added(merged, base)
+    }
+
+    /// Print `merged`, which matched `base` at `range` in the source.
+    fn print(printer: &mut Printer<'_>, merged: &Self, base: &Self, range: Range<usize>) {
+        printer.node(merged, base, range);
+    }
+}
+
+/// The node without its children, if it is an inline module, impl or trait.
+fn without_children(node: &Item) -> Option<Item> {
+    let mut node = node.clone();
+    match &mut node {
+        Item::Mod(item) => item.content.as_mut()?.1.clear(),
+        Item::Impl(item) => item.items.clear(),
+        Item::Trait(item) => item.items.clear(),
+        _ => return None,
+    }
+    Some(node)
+}
+
+impl Preserve for Item {
+    fn unparse(&self) -> String {
+        prettyplease::unparse(&File {
+            shebang: None,
+            attrs: vec![],
+            items: vec![self.clone()],
+        })
+    }
+
+    fn matches(merged: &Self, base: &Self) -> bool {
+        if only_attrs_added(merged, base) {
+            return true;
+        }
+        match (without_children(merged), without_children(base)) {
+            (Some(merged), Some(base)) => only_attrs_added(&merged, &base),
+            _ => false,
+        }
+    }
+
+    fn print(printer: &mut Printer<'_>, merged: &Self, base: &Self, range: Range<usize>) {
+        if only_attrs_added(merged, base) {
+            return printer.node(merged, base, range);
+        }
+        let braces = match (merged, base) {
+            (Item::Mod(m), Item::Mod(f)) => match (&m.content, &f.content) {
+                (Some((_, m_items)), Some((brace, f_items))) => {
+                    Some((brace.span, Children::Items(m_items, f_items)))
+                }
+                _ => None,
+            },
+            (Item::Impl(m), Item::Impl(f)) => {
+                Some((f.brace_token.span, Children::ImplItems(&m.items, &f.items)))
+            }
+            (Item::Trait(m), Item::Trait(f)) => {
+                Some((f.brace_token.span, Children::TraitItems(&m.items, &f.items)))
+            }
+            _ => None,
+        };
+        let Some((brace, children)) = braces else {
+            return printer.node(merged, base, range);
+        };
+        let start = printer.attrs(merged, base, &range);
+        let open = printer.range(brace.open()).end;
+        let close = printer.range(brace.close()).start;
+        printer.copy(start..open);
+        let end_indent = printer.indent(close);
+        let outer = printer.indent(range.start);
+        let region = open..close;
+        match children {
+            Children::Items(merged, base) => {
+                let indent = child_indent(printer, base, outer);
+                printer.list(merged, base, region, &indent, end_indent);
+            }
+            Children::ImplItems(merged, base) => {
+                let indent = child_indent(printer, base, outer);
+                printer.list(merged, base, region, &indent, end_indent);
+            }
+            Children::TraitItems(merged, base) => {
+                let indent = child_indent(printer, base, outer);
+                printer.list(merged, base, region, &indent, end_indent);
+            }
+        }
+        printer.copy(close..range.end);
+    }
+}
+
+enum Children<'a> {
+    Items(&'a [Item], &'a [Item]),
+    ImplItems(&'a [ImplItem], &'a [ImplItem]),
+    TraitItems(&'a [TraitItem], &'a [TraitItem]),
+}
+
+/// The indentation of the children of a node, taken from its first child.
+fn child_indent<T: Preserve>(printer: &Printer<'_>, base: &[T], outer: &str) -> String {
+    match base.first() {
+        Some(child) => printer
+            .indent(printer.range(child.span()).start)
+            .to_string(),
+        None => format!("{outer}    "),
+    }
+}
+
+/// Print the node on its own inside a wrapper item, and take the lines
+/// between the wrapper's braces.
+fn unparse_in(wrapper: Item) -> String {
+    let text = Item::unparse(&wrapper);
+    let lines: Vec<_> = text.lines().collect();
+    let mut res = String::new();
+    // Skip the lines of the wrapper, which are missing if it's empty
+    let inner = lines
+        .get(1..lines.len().saturating_sub(1))
+        .unwrap_or_default();
+    for line in inner {
+        res.push_str(line.strip_prefix("    ").unwrap_or(line));
+        res.push('\n');
+    }
+    res
+}
+
+impl Preserve for ImplItem {
+    fn unparse(&self) -> String {
+        unparse_in(parse_quote!(impl S { #self }))
+    }
+}
+
+impl Preserve for TraitItem {
+    fn unparse(&self) -> String {
+        unparse_in(parse_quote!(trait T { #self }))
+    }
+}
@@ -7,10 +7,10 @@ use syn::Attribute;
 
 use crate::merge_by_extracting_first;
 
-use super::{Cfgs, Merge};
+use super::{Cfgs, Error, ErrorKind, Merge, MergeContext};
 
 impl Merge for TokenStream {
-    fn top_level_eq(&self, other: &Self) -> bool {
+    fn top_level_eq(&self, other: &Self, cx: &MergeContext<'_>) -> bool {
         let mut x_iter = self.clone().into_iter();
         let mut y_iter = other.clone().into_iter();
         loop {
@@ -18,15 +18,18 @@ impl Merge for TokenStream {
                 return y_iter.next().is_none();
             };
             let Some(y) = y_iter.next() else {
-                return y_iter.next().is_none();
+                return false;
             };
-            if !x.top_level_eq(&y) {
+            if !cx.top_level_eq(&x, &y) {
                 return false;
             }
         }
     }
 
-    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(iter: I) -> Self
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(
+        iter: I,
+        _cx: &MergeContext<'_>,
+    ) -> Result<Self, Error>
     where
         Self: 'a,
         I::IntoIter: Clone,
@@ -34,9 +37,21 @@ impl Merge for TokenStream {
         merge_by_extracting_first(iter)
     }
 
-    fn add_attr(&mut self, _attr: Attribute) {
+    fn add_attr(&mut self, _attr: Attribute, _cx: &MergeContext<'_>) -> Result<(), Error> {
         // TODO: Maybe implement this by not considering the higher-level items equal?
-        unimplemented!()
+        let err = Error::new(
+            ErrorKind::Unsupported,
+            "can't add `#[cfg]` to verbatim tokens",
+        );
+        let mut tokens = self.clone().into_iter();
+        let Some(first) = tokens.next() else {
+            return Err(err);
+        };
+        let span = match tokens.last() {
+            Some(last) => first.span().join(last.span()).unwrap_or(first.span()),
+            None => first.span(),
+        };
+        Err(err.with_span(span))
     }
 }
 
@@ -50,11 +65,14 @@ impl_merge_enum! {
 }
 
 impl Merge for Group {
-    fn top_level_eq(&self, other: &Self) -> bool {
-        self.delimiter() == other.delimiter() && self.stream().top_level_eq(&other.stream())
+    fn top_level_eq(&self, other: &Self, cx: &MergeContext<'_>) -> bool {
+        self.delimiter() == other.delimiter() && cx.top_level_eq(&self.stream(), &other.stream())
     }
 
-    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(iter: I) -> Self
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(
+        iter: I,
+        _cx: &MergeContext<'_>,
+    ) -> Result<Self, Error>
     where
         Self: 'a,
         I::IntoIter: Clone,
@@ -64,11 +82,14 @@ impl Merge for Group {
 }
 
 impl Merge for Ident {
-    fn top_level_eq(&self, other: &Self) -> bool {
+    fn top_level_eq(&self, other: &Self, _cx: &MergeContext<'_>) -> bool {
         self == other
     }
 
-    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(iter: I) -> Self
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(
+        iter: I,
+        _cx: &MergeContext<'_>,
+    ) -> Result<Self, Error>
     where
         Self: 'a,
         I::IntoIter: Clone,
@@ -78,11 +99,14 @@ impl Merge for Ident {
 }
 
 impl Merge for Punct {
-    fn top_level_eq(&self, other: &Self) -> bool {
+    fn top_level_eq(&self, other: &Self, _cx: &MergeContext<'_>) -> bool {
         self.as_char() == other.as_char() && self.spacing() == other.spacing()
     }
 
-    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(iter: I) -> Self
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(
+        iter: I,
+        _cx: &MergeContext<'_>,
+    ) -> Result<Self, Error>
     where
         Self: 'a,
         I::IntoIter: Clone,
@@ -92,11 +116,14 @@ impl Merge for Punct {
 }
 
 impl Merge for Literal {
-    fn top_level_eq(&self, other: &Self) -> bool {
+    fn top_level_eq(&self, other: &Self, _cx: &MergeContext<'_>) -> bool {
         self.to_string() == other.to_string()
     }
 
-    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(iter: I) -> Self
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a Cfgs)>>(
+        iter: I,
+        _cx: &MergeContext<'_>,
+    ) -> Result<Self, Error>
     where
         Self: 'a,
         I::IntoIter: Clone,
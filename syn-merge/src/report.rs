@@ -0,0 +1,134 @@
+//! A standalone HTML page showing how the configurations differ.
+use std::fmt::Write;
+
+use syn::{File, Item};
+
+use crate::manifest::{build, is_other_version};
+use crate::source_map::{merge_and_collect, Node};
+use crate::{Cfgs, Decision, Error, MappedItem, MergeOptions};
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; vertical-align: top; }
+td.yes { background: #dfd; text-align: center; }
+td.no { background: #fdd; text-align: center; }
+table.diff td { font-family: monospace; white-space: pre; border: none; }
+table.diff tr.differs { background: #ffd; }
+";
+
+/// Merge the files, and render an HTML page that lists the merged items
+/// along with which configurations include them.
+///
+/// Items that were duplicated because they differ between the inputs get an
+/// expandable diff of each version, formatted with `prettyplease`.
+///
+/// Requires the `pretty` feature.
+pub fn html_report(input: &[(File, Cfgs)], options: &MergeOptions) -> Result<String, Error> {
+    let (_, map, nodes) = merge_and_collect(input, options, true)?;
+    let manifest = build(input.len(), &map, &nodes);
+
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str("<title>syn-merge report</title>\n");
+    let _ = writeln!(out, "<style>\n{STYLE}</style>\n</head>\n<body>");
+    let _ = writeln!(
+        out,
+        "<p>{} items, {} shared between all configurations.</p>",
+        manifest.shared() + manifest.specific(),
+        manifest.shared(),
+    );
+    out.push_str("<table>\n<tr><th>Item</th>");
+    for (_, cfgs) in input {
+        let _ = write!(
+            out,
+            "<th><code>{}</code></th>",
+            escape(&cfgs.predicate.to_string())
+        );
+    }
+    out.push_str("</tr>\n");
+
+    let mut diffed: Vec<&MappedItem> = Vec::new();
+    for (idx, item) in manifest.items.iter().enumerate() {
+        if item.path.len() != 1 {
+            continue;
+        }
+        let _ = write!(out, "<tr><td><code>{}</code>", escape(&item.name[0]));
+        if let Decision::Duplicated { .. } = item.decision {
+            let mapped = &map.items[idx];
+            if !diffed.iter().any(|other| other.name == mapped.name) {
+                diffed.push(mapped);
+                let versions: Vec<_> = map
+                    .items
+                    .iter()
+                    .zip(&nodes)
+                    .filter(|(other, _)| {
+                        other.path == mapped.path || is_other_version(mapped, other)
+                    })
+                    .collect();
+                diff(&mut out, input, &versions);
+            }
+        }
+        out.push_str("</td>");
+        for input in 0..input.len() {
+            if item.origins.iter().any(|origin| origin.input == input) {
+                out.push_str("<td class=\"yes\">&#10003;</td>");
+            } else {
+                out.push_str("<td class=\"no\"></td>");
+            }
+        }
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</table>\n</body>\n</html>\n");
+    Ok(out)
+}
+
+/// Write an n-way diff of each version of a duplicated item.
+fn diff(out: &mut String, input: &[(File, Cfgs)], versions: &[(&MappedItem, &Node)]) {
+    let lines: Vec<Vec<String>> = versions
+        .iter()
+        .map(|(_, node)| {
+            let text = match syn::parse2::<Item>(node.tokens.clone()) {
+                Ok(item) => prettyplease::unparse(&File {
+                    shebang: None,
+                    attrs: vec![],
+                    items: vec![item],
+                }),
+                Err(_) => node.tokens.to_string(),
+            };
+            text.lines().map(str::to_string).collect()
+        })
+        .collect();
+    let sequences: Vec<&[String]> = lines.iter().map(Vec::as_slice).collect();
+
+    out.push_str("<details><summary>Differences</summary>\n<table class=\"diff\">\n<tr>");
+    for (item, _) in versions {
+        let cfgs: Vec<_> = item
+            .origins
+            .iter()
+            .map(|origin| escape(&input[origin.input].1.predicate.to_string()))
+            .collect();
+        let _ = write!(out, "<th><code>{}</code></th>", cfgs.join(", "));
+    }
+    out.push_str("</tr>\n");
+    for indexes in multidiff::multidiff_indexes(&sequences) {
+        if indexes.iter().all(Option::is_some) {
+            out.push_str("<tr>");
+        } else {
+            out.push_str("<tr class=\"differs\">");
+        }
+        for (idx, lines) in indexes.iter().zip(&lines) {
+            let line = idx.map_or("", |idx| &lines[idx]);
+            let _ = write!(out, "<td>{}</td>", escape(line));
+        }
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</table>\n</details>");
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
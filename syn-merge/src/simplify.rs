@@ -0,0 +1,88 @@
+//! Simplify `cfg`s on nested items using the `cfg`s of their parents.
+use syn::visit_mut::{self, VisitMut};
+use syn::{File, ForeignItem, ImplItem, Item, Stmt, TraitItem};
+
+use crate::{Merge, Predicate};
+
+/// Simplify the `#[cfg]` attributes of items inside modules, impls, traits,
+/// foreign modules and blocks, under the `#[cfg]` of the enclosing item.
+///
+/// E.g. `#[cfg(all(unix, target_os = "linux"))]` inside a
+/// `#[cfg(unix)] mod sys` becomes `#[cfg(target_os = "linux")]`, and `cfg`s
+/// that always hold in the context are removed.
+///
+/// Multiple `#[cfg]` attributes on a nested item are combined into one.
+/// Attributes that fail to parse as a `cfg` predicate are left as-is.
+pub fn simplify_nested_cfgs(mut file: File) -> File {
+    simplify_cfgs_under(&mut file, Predicate::TRUE, true);
+    file
+}
+
+/// Simplify all `#[cfg]` attributes in the file under `context`, and if
+/// `nested`, also under the `#[cfg]`s of their parents.
+pub(crate) fn simplify_cfgs_under(file: &mut File, context: Predicate, nested: bool) {
+    Simplifier { context, nested }.visit_file_mut(file);
+}
+
+struct Simplifier {
+    context: Predicate,
+    nested: bool,
+}
+
+impl Simplifier {
+    fn with_node<T: Merge>(&mut self, node: &mut T, visit: impl FnOnce(&mut Self, &mut T)) {
+        let Some(attrs) = node.attrs_mut() else {
+            return visit(self, node);
+        };
+        let Some(Ok(own)) = Predicate::from_attributes(attrs) else {
+            return visit(self, node);
+        };
+
+        if !self.context.is_true() {
+            let simplified = own.simplify_under(&self.context);
+            let idx = attrs
+                .iter()
+                .position(|attr| attr.path().is_ident("cfg"))
+                .unwrap();
+            attrs.retain(|attr| !attr.path().is_ident("cfg"));
+            if !simplified.is_true() {
+                attrs.insert(idx, simplified.attribute());
+            }
+        }
+
+        if !self.nested {
+            return visit(self, node);
+        }
+        let parent = self.context.clone();
+        self.context = parent.clone().and(own);
+        visit(self, node);
+        self.context = parent;
+    }
+}
+
+impl VisitMut for Simplifier {
+    fn visit_item_mut(&mut self, node: &mut Item) {
+        self.with_node(node, visit_mut::visit_item_mut);
+    }
+
+    fn visit_impl_item_mut(&mut self, node: &mut ImplItem) {
+        self.with_node(node, visit_mut::visit_impl_item_mut);
+    }
+
+    fn visit_trait_item_mut(&mut self, node: &mut TraitItem) {
+        self.with_node(node, visit_mut::visit_trait_item_mut);
+    }
+
+    fn visit_foreign_item_mut(&mut self, node: &mut ForeignItem) {
+        self.with_node(node, visit_mut::visit_foreign_item_mut);
+    }
+
+    fn visit_stmt_mut(&mut self, node: &mut Stmt) {
+        if let Stmt::Item(_) = node {
+            // Handled by `visit_item_mut`
+            visit_mut::visit_stmt_mut(self, node);
+        } else {
+            self.with_node(node, visit_mut::visit_stmt_mut);
+        }
+    }
+}
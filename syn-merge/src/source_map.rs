@@ -0,0 +1,321 @@
+//! Map the items of a merged file back to the inputs they came from.
+use std::any::Any;
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+
+use proc_macro2::{Span, TokenStream};
+use syn::spanned::Spanned;
+use syn::{
+    parse_quote, Attribute, File, ForeignItem, ImplItem, Item, LitInt, TraitItem, Visibility,
+};
+
+use crate::verify::Describe;
+use crate::{merge_files_impl, Cfgs, Error, Merge, MergeContext, MergeOptions, Predicate};
+
+/// The attribute that items are tagged with while merging, pointing to
+/// their recorded origins.
+const MARKER: &str = "__syn_merge_origin";
+
+/// Where each item of a merged file came from, see
+/// [`merge_files_with_source_map`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourceMap {
+    /// The items, with each item followed by its children.
+    pub items: Vec<MappedItem>,
+}
+
+impl SourceMap {
+    /// The item at `path`, see [`MappedItem::path`].
+    pub fn get(&self, path: &[usize]) -> Option<&MappedItem> {
+        self.items.iter().find(|item| item.path == path)
+    }
+}
+
+/// An item in the merged file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MappedItem {
+    /// The indices leading to the item, e.g. `[2, 0]` is the first item in
+    /// the third item of the file, which is a module, `impl`, trait or
+    /// `extern` block.
+    pub path: Vec<usize>,
+    /// The descriptions of the item and its parents, like
+    /// `["mod sys", "fn foo"]`.
+    pub name: Vec<String>,
+    /// The inputs that the item came from, in order.
+    pub origins: Vec<Origin>,
+}
+
+/// An input that an item came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Origin {
+    /// The index of the input.
+    pub input: usize,
+    /// The lines of the item in that input, starting at 1. Only known if
+    /// the input was parsed from source, and with the `diagnostics` feature.
+    pub lines: Option<RangeInclusive<usize>>,
+}
+
+/// The origins of the items merged so far, indexed by their marker.
+#[derive(Debug)]
+pub(crate) struct Origins {
+    recorded: RefCell<Vec<Vec<Origin>>>,
+}
+
+impl Origins {
+    /// Remember where `node` came from, if it's an item.
+    pub(crate) fn record<T: Merge>(
+        &self,
+        node: &mut T,
+        values: &[(&T, &Cfgs)],
+        cx: &MergeContext<'_>,
+    ) {
+        if span_of(&*node).is_none() {
+            return;
+        }
+        let Some(attrs) = node.attrs_mut() else {
+            return;
+        };
+        let origins = values
+            .iter()
+            .filter_map(|(value, cfgs)| {
+                let input = cx.input_index(cfgs)?;
+                let lines = span_of(*value).and_then(lines);
+                Some(Origin { input, lines })
+            })
+            .collect();
+        let mut recorded = self.recorded.borrow_mut();
+        let marker = LitInt::new(&recorded.len().to_string(), Span::call_site());
+        let name = quote::format_ident!("{}", MARKER);
+        attrs.push(parse_quote!(#[#name(#marker)]));
+        recorded.push(origins);
+    }
+}
+
+/// The span of `node` if it's an item, impl item, trait item or foreign
+/// item.
+fn span_of<T: 'static>(node: &T) -> Option<Span> {
+    let node = node as &dyn Any;
+    if let Some(node) = node.downcast_ref::<Item>() {
+        Some(node.span())
+    } else if let Some(node) = node.downcast_ref::<ImplItem>() {
+        Some(node.span())
+    } else if let Some(node) = node.downcast_ref::<TraitItem>() {
+        Some(node.span())
+    } else {
+        node.downcast_ref::<ForeignItem>().map(Spanned::span)
+    }
+}
+
+/// The lines that `span` covers, if it points into a parsed source.
+#[cfg(feature = "diagnostics")]
+fn lines(span: Span) -> Option<RangeInclusive<usize>> {
+    span.source_text()?;
+    Some(span.start().line..=span.end().line)
+}
+
+#[cfg(not(feature = "diagnostics"))]
+fn lines(_span: Span) -> Option<RangeInclusive<usize>> {
+    None
+}
+
+/// Like [`merge_files_with_options`][crate::merge_files_with_options], but
+/// also return where each item of the merged file came from.
+///
+/// Items that are generated rather than merged, like the `compile_error!`
+/// from [`MergeOptions::compile_error`], have no origins.
+pub fn merge_files_with_source_map(
+    input: &[(File, Cfgs)],
+    options: &MergeOptions,
+) -> Result<(File, SourceMap), Error> {
+    let (file, map, _) = merge_and_collect(input, options, false)?;
+    Ok((file, map))
+}
+
+/// A merged node, as needed by the [`Manifest`][crate::Manifest].
+pub(crate) struct Node {
+    pub(crate) public: bool,
+    pub(crate) predicate: Option<String>,
+    /// The tokens of the node without its attributes.
+    pub(crate) tokens: TokenStream,
+    /// The node without its attributes.
+    pub(crate) bare: AnyNode,
+}
+
+/// An item, impl item, trait item or foreign item.
+pub(crate) enum AnyNode {
+    Item(Item),
+    ImplItem(ImplItem),
+    TraitItem(TraitItem),
+    ForeignItem(ForeignItem),
+}
+
+impl AnyNode {
+    fn new<T: 'static>(node: T) -> Option<Self> {
+        let node: Box<dyn Any> = Box::new(node);
+        let node = match node.downcast::<Item>() {
+            Ok(node) => return Some(Self::Item(*node)),
+            Err(node) => node,
+        };
+        let node = match node.downcast::<ImplItem>() {
+            Ok(node) => return Some(Self::ImplItem(*node)),
+            Err(node) => node,
+        };
+        let node = match node.downcast::<TraitItem>() {
+            Ok(node) => return Some(Self::TraitItem(*node)),
+            Err(node) => node,
+        };
+        node.downcast::<ForeignItem>()
+            .ok()
+            .map(|node| Self::ForeignItem(*node))
+    }
+}
+
+/// Merge the files and build the source map, along with the merged node at
+/// each entry if `nodes` is set.
+pub(crate) fn merge_and_collect(
+    input: &[(File, Cfgs)],
+    options: &MergeOptions,
+    nodes: bool,
+) -> Result<(File, SourceMap, Vec<Node>), Error> {
+    let origins = Origins {
+        recorded: RefCell::new(Vec::new()),
+    };
+    let mut file = merge_files_impl(input, options, Some(&origins))?;
+    let mut collector = Collector {
+        recorded: origins.recorded.take(),
+        map: SourceMap::default(),
+        nodes: if nodes { Some(Vec::new()) } else { None },
+        path: Vec::new(),
+        name: Vec::new(),
+        public: false,
+    };
+    collector.items(&mut file.items);
+    Ok((file, collector.map, collector.nodes.unwrap_or_default()))
+}
+
+/// Removes the markers from the merged items and collects their origins.
+struct Collector {
+    recorded: Vec<Vec<Origin>>,
+    map: SourceMap,
+    nodes: Option<Vec<Node>>,
+    path: Vec<usize>,
+    name: Vec<String>,
+    /// Whether the parent of the current list is public.
+    public: bool,
+}
+
+impl Collector {
+    fn items(&mut self, items: &mut [Item]) {
+        self.list(items, |this, item| match item {
+            Item::Mod(item) => {
+                if let Some((_, items)) = &mut item.content {
+                    this.items(items);
+                }
+            }
+            Item::Impl(item) => this.list(&mut item.items, |_, _| {}),
+            Item::Trait(item) => this.list(&mut item.items, |_, _| {}),
+            Item::ForeignMod(item) => this.list(&mut item.items, |_, _| {}),
+            _ => {}
+        });
+    }
+
+    fn list<T: Merge + Describe>(
+        &mut self,
+        nodes: &mut [T],
+        mut children: impl FnMut(&mut Self, &mut T),
+    ) {
+        for (idx, node) in nodes.iter_mut().enumerate() {
+            let origins = node
+                .attrs_mut()
+                .and_then(take_marker)
+                .and_then(|marker| self.recorded.get_mut(marker))
+                .map(std::mem::take)
+                .unwrap_or_default();
+            self.path.push(idx);
+            self.name.push(node.describe());
+            self.map.items.push(MappedItem {
+                path: self.path.clone(),
+                name: self.name.clone(),
+                origins,
+            });
+            let parent_public = self.public;
+            self.public = match visibility(&*node) {
+                Some(vis) => matches!(vis, Visibility::Public(_)),
+                None => parent_public,
+            };
+            let public = self.public;
+            let predicate = match Predicate::from_attributes(node.attrs()) {
+                Some(Ok(predicate)) => Some(predicate.to_string()),
+                _ => None,
+            };
+            let position = self.nodes.as_ref().map(Vec::len);
+            // Collect the children first, to remove their markers
+            children(self, node);
+            if let (Some(nodes), Some(position)) = (&mut self.nodes, position) {
+                let mut bare = node.clone();
+                if let Some(attrs) = bare.attrs_mut() {
+                    attrs.clear();
+                }
+                let tokens = bare.to_token_stream();
+                nodes.insert(
+                    position,
+                    Node {
+                        public,
+                        predicate,
+                        tokens,
+                        bare: AnyNode::new(bare).expect("collected nodes are items"),
+                    },
+                );
+            }
+            self.public = parent_public;
+            self.path.pop();
+            self.name.pop();
+        }
+    }
+}
+
+/// The visibility of `node`, if it's an item that has one.
+fn visibility<T: 'static>(node: &T) -> Option<&Visibility> {
+    let node = node as &dyn Any;
+    if let Some(node) = node.downcast_ref::<Item>() {
+        match node {
+            Item::Const(item) => Some(&item.vis),
+            Item::Enum(item) => Some(&item.vis),
+            Item::ExternCrate(item) => Some(&item.vis),
+            Item::Fn(item) => Some(&item.vis),
+            Item::Mod(item) => Some(&item.vis),
+            Item::Static(item) => Some(&item.vis),
+            Item::Struct(item) => Some(&item.vis),
+            Item::Trait(item) => Some(&item.vis),
+            Item::TraitAlias(item) => Some(&item.vis),
+            Item::Type(item) => Some(&item.vis),
+            Item::Union(item) => Some(&item.vis),
+            Item::Use(item) => Some(&item.vis),
+            _ => None,
+        }
+    } else if let Some(node) = node.downcast_ref::<ImplItem>() {
+        match node {
+            ImplItem::Const(item) => Some(&item.vis),
+            ImplItem::Fn(item) => Some(&item.vis),
+            ImplItem::Type(item) => Some(&item.vis),
+            _ => None,
+        }
+    } else {
+        match node.downcast_ref::<ForeignItem>()? {
+            ForeignItem::Fn(item) => Some(&item.vis),
+            ForeignItem::Static(item) => Some(&item.vis),
+            ForeignItem::Type(item) => Some(&item.vis),
+            _ => None,
+        }
+    }
+}
+
+/// Remove the marker attribute and return its value.
+fn take_marker(attrs: &mut Vec<Attribute>) -> Option<usize> {
+    let idx = attrs.iter().position(|attr| attr.path().is_ident(MARKER))?;
+    let attr = attrs.remove(idx);
+    attr.parse_args::<LitInt>().ok()?.base10_parse().ok()
+}
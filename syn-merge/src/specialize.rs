@@ -0,0 +1,290 @@
+//! Evaluate the `cfg`s in a merged file for a single configuration.
+use std::collections::BTreeSet;
+use std::mem;
+
+use quote::quote;
+
+use syn::punctuated::Punctuated;
+use syn::visit_mut::{self, VisitMut};
+use syn::{
+    parse_quote, Attribute, Block, Expr, ExprArray, ExprCall, ExprMatch, ExprMethodCall,
+    ExprStruct, ExprTuple, FieldsNamed, FieldsUnnamed, File, ItemEnum, ItemForeignMod, ItemImpl,
+    ItemMod, ItemTrait, PatSlice, PatStruct, PatTuple, PatTupleStruct, Signature,
+};
+
+use crate::{Cfgs, Merge, Predicate};
+
+/// Specialize ("unmerge") a merged file for a single configuration.
+///
+/// This is the inverse of [`merge_files`][crate::merge_files]: It produces
+/// the file that the compiler would see when compiling with `cfgs`.
+///
+/// - Nodes whose `#[cfg]` doesn't hold are removed, and the `#[cfg]`
+///   attributes of the remaining nodes are removed.
+/// - `#[cfg_attr(pred, attrs...)]` is replaced by `attrs` if the predicate
+///   holds, and removed otherwise.
+/// - `cfg!(pred)` is replaced by `true` or `false`.
+///
+/// This is done for items, statements, fields, variants, match arms,
+/// function parameters and the elements of arrays, tuples and calls.
+/// Predicates that fail to parse are left as-is.
+pub fn specialize(file: &File, cfgs: &Cfgs) -> File {
+    let mut file = file.clone();
+    Specializer { cfgs, known: None }.visit_file_mut(&mut file);
+    file
+}
+
+/// Like [`specialize`], but only the names and key-value pairs that
+/// `configs` mention are evaluated.
+///
+/// The other parts of the predicates are kept, so that e.g.
+/// `#[cfg(all(foo, feature = "x"))]` becomes `#[cfg(feature = "x")]` when
+/// specializing for `foo`, instead of being removed.
+pub(crate) fn specialize_within(file: &File, cfgs: &Cfgs, configs: &[Cfgs]) -> File {
+    let known = configs
+        .iter()
+        .flat_map(Cfgs::atoms)
+        .chain(cfgs.atoms())
+        .cloned()
+        .collect();
+    let mut file = file.clone();
+    Specializer {
+        cfgs,
+        known: Some(known),
+    }
+    .visit_file_mut(&mut file);
+    file
+}
+
+struct Specializer<'a> {
+    cfgs: &'a Cfgs,
+    /// The atoms that can be evaluated, or `None` if all of them can.
+    known: Option<BTreeSet<Predicate>>,
+}
+
+impl Specializer<'_> {
+    /// The part of `predicate` that isn't decided by the configuration.
+    fn residual(&self, predicate: &Predicate) -> Predicate {
+        predicate.partial_eval(&|atom| {
+            let known = self
+                .known
+                .as_ref()
+                .map_or(true, |known| known.contains(atom));
+            if known {
+                Some(self.cfgs.holds(atom))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Whether the node is active, removing its `#[cfg]` attributes if so.
+    ///
+    /// A `#[cfg]` for the undecided part of the predicate is kept. Nodes with
+    /// `cfg`s that can't be parsed are kept as-is.
+    fn keep<T: Merge>(&self, node: &mut T) -> bool {
+        let Some(attrs) = node.attrs_mut() else {
+            return true;
+        };
+        self.expand_cfg_attrs(attrs);
+        let Some(Ok(predicate)) = Predicate::from_attributes(attrs) else {
+            return true;
+        };
+        let position = attrs
+            .iter()
+            .position(|attr| attr.path().is_ident("cfg"))
+            .unwrap_or(0);
+        attrs.retain(|attr| !attr.path().is_ident("cfg"));
+        let residual = self.residual(&predicate);
+        if residual.is_false() {
+            return false;
+        }
+        if !residual.is_true() {
+            attrs.insert(position, residual.attribute());
+        }
+        true
+    }
+
+    fn expand_cfg_attrs(&self, attrs: &mut Vec<Attribute>) {
+        *attrs = mem::take(attrs)
+            .into_iter()
+            .flat_map(|attr| self.expand_cfg_attr(attr))
+            .collect();
+    }
+
+    fn expand_cfg_attr(&self, attr: Attribute) -> Vec<Attribute> {
+        let Some(Ok((predicate, metas))) = Predicate::from_cfg_attr(&attr) else {
+            return vec![attr];
+        };
+        let residual = self.residual(&predicate);
+        if residual.is_false() {
+            return vec![];
+        }
+        if !residual.is_true() {
+            let metas = metas.iter();
+            return vec![Attribute {
+                meta: parse_quote!(cfg_attr(#residual, #(#metas),*)),
+                ..attr
+            }];
+        }
+        metas
+            .into_iter()
+            .flat_map(|meta| {
+                self.expand_cfg_attr(Attribute {
+                    meta,
+                    ..attr.clone()
+                })
+            })
+            .collect()
+    }
+
+    fn keep_value<T: Merge>(&self, mut value: T) -> Option<T> {
+        if self.keep(&mut value) {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    fn retain<T: Merge>(&self, values: &mut Vec<T>) {
+        *values = mem::take(values)
+            .into_iter()
+            .filter_map(|value| self.keep_value(value))
+            .collect();
+    }
+
+    fn retain_punctuated<T: Merge, P: Default>(&self, values: &mut Punctuated<T, P>) {
+        let trailing = values.trailing_punct();
+        let mut res: Punctuated<T, P> = mem::take(values)
+            .into_iter()
+            .filter_map(|value| self.keep_value(value))
+            .collect();
+        if trailing && !res.empty_or_trailing() {
+            res.push_punct(P::default());
+        }
+        *values = res;
+    }
+}
+
+impl VisitMut for Specializer<'_> {
+    fn visit_file_mut(&mut self, node: &mut File) {
+        self.expand_cfg_attrs(&mut node.attrs);
+        self.retain(&mut node.items);
+        visit_mut::visit_file_mut(self, node);
+    }
+
+    fn visit_expr_mut(&mut self, node: &mut Expr) {
+        if let Expr::Macro(expr) = node {
+            if expr.mac.path.is_ident("cfg") {
+                if let Ok(predicate) = expr.mac.parse_body::<Predicate>() {
+                    let residual = self.residual(&predicate);
+                    if residual.is_true() || residual.is_false() {
+                        let value = residual.is_true();
+                        *node = parse_quote!(#value);
+                    } else {
+                        expr.mac.tokens = quote!(#residual);
+                    }
+                    return;
+                }
+            }
+        }
+        visit_mut::visit_expr_mut(self, node);
+    }
+
+    fn visit_item_mod_mut(&mut self, node: &mut ItemMod) {
+        if let Some((_, items)) = &mut node.content {
+            self.retain(items);
+        }
+        visit_mut::visit_item_mod_mut(self, node);
+    }
+
+    fn visit_item_foreign_mod_mut(&mut self, node: &mut ItemForeignMod) {
+        self.retain(&mut node.items);
+        visit_mut::visit_item_foreign_mod_mut(self, node);
+    }
+
+    fn visit_item_impl_mut(&mut self, node: &mut ItemImpl) {
+        self.retain(&mut node.items);
+        visit_mut::visit_item_impl_mut(self, node);
+    }
+
+    fn visit_item_trait_mut(&mut self, node: &mut ItemTrait) {
+        self.retain(&mut node.items);
+        visit_mut::visit_item_trait_mut(self, node);
+    }
+
+    fn visit_item_enum_mut(&mut self, node: &mut ItemEnum) {
+        self.retain_punctuated(&mut node.variants);
+        visit_mut::visit_item_enum_mut(self, node);
+    }
+
+    fn visit_block_mut(&mut self, node: &mut Block) {
+        self.retain(&mut node.stmts);
+        visit_mut::visit_block_mut(self, node);
+    }
+
+    fn visit_fields_named_mut(&mut self, node: &mut FieldsNamed) {
+        self.retain_punctuated(&mut node.named);
+        visit_mut::visit_fields_named_mut(self, node);
+    }
+
+    fn visit_fields_unnamed_mut(&mut self, node: &mut FieldsUnnamed) {
+        self.retain_punctuated(&mut node.unnamed);
+        visit_mut::visit_fields_unnamed_mut(self, node);
+    }
+
+    fn visit_signature_mut(&mut self, node: &mut Signature) {
+        self.retain_punctuated(&mut node.inputs);
+        visit_mut::visit_signature_mut(self, node);
+    }
+
+    fn visit_expr_array_mut(&mut self, node: &mut ExprArray) {
+        self.retain_punctuated(&mut node.elems);
+        visit_mut::visit_expr_array_mut(self, node);
+    }
+
+    fn visit_expr_call_mut(&mut self, node: &mut ExprCall) {
+        self.retain_punctuated(&mut node.args);
+        visit_mut::visit_expr_call_mut(self, node);
+    }
+
+    fn visit_expr_match_mut(&mut self, node: &mut ExprMatch) {
+        self.retain(&mut node.arms);
+        visit_mut::visit_expr_match_mut(self, node);
+    }
+
+    fn visit_expr_method_call_mut(&mut self, node: &mut ExprMethodCall) {
+        self.retain_punctuated(&mut node.args);
+        visit_mut::visit_expr_method_call_mut(self, node);
+    }
+
+    fn visit_expr_struct_mut(&mut self, node: &mut ExprStruct) {
+        self.retain_punctuated(&mut node.fields);
+        visit_mut::visit_expr_struct_mut(self, node);
+    }
+
+    fn visit_expr_tuple_mut(&mut self, node: &mut ExprTuple) {
+        self.retain_punctuated(&mut node.elems);
+        visit_mut::visit_expr_tuple_mut(self, node);
+    }
+
+    fn visit_pat_slice_mut(&mut self, node: &mut PatSlice) {
+        self.retain_punctuated(&mut node.elems);
+        visit_mut::visit_pat_slice_mut(self, node);
+    }
+
+    fn visit_pat_struct_mut(&mut self, node: &mut PatStruct) {
+        self.retain_punctuated(&mut node.fields);
+        visit_mut::visit_pat_struct_mut(self, node);
+    }
+
+    fn visit_pat_tuple_mut(&mut self, node: &mut PatTuple) {
+        self.retain_punctuated(&mut node.elems);
+        visit_mut::visit_pat_tuple_mut(self, node);
+    }
+
+    fn visit_pat_tuple_struct_mut(&mut self, node: &mut PatTupleStruct) {
+        self.retain_punctuated(&mut node.elems);
+        visit_mut::visit_pat_tuple_struct_mut(self, node);
+    }
+}
@@ -36,43 +36,156 @@ impl_merge_eq!(syn::Visibility);
 impl_merge_eq!(syn::Macro);
 
 impl crate::Merge for syn::Fields {
-    fn top_level_eq(&self, other: &Self) -> bool {
+    fn top_level_eq(&self, other: &Self, cx: &crate::MergeContext<'_>) -> bool {
         match (self, other) {
-            (Self::Named(this), Self::Named(other)) => this.top_level_eq(other),
-            (Self::Unnamed(this), Self::Unnamed(other)) => this.top_level_eq(other),
+            (Self::Named(this), Self::Named(other)) => cx.top_level_eq(this, other),
+            (Self::Unnamed(this), Self::Unnamed(other)) => cx.top_level_eq(this, other),
             (Self::Unit, Self::Unit) => true,
             _ => false,
         }
     }
 
-    fn add_attr(&mut self, attr: crate::Attribute) {
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a crate::Cfgs)>>(
+        iter: I,
+        cx: &crate::MergeContext<'_>,
+    ) -> Result<Self, crate::Error>
+    where
+        Self: 'a,
+        I::IntoIter: Clone,
+    {
+        let iter = iter.into_iter();
+        let first = crate::first(iter.clone())?;
+        // Collected to avoid infinitely nested iterator types
+        Ok(match first {
+            Self::Named(_) => Self::Named(
+                cx.merge(
+                    iter.filter_map(|(this, cfgs)| match this {
+                        Self::Named(this) => Some((this, cfgs)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+                )?,
+            ),
+            Self::Unnamed(_) => Self::Unnamed(
+                cx.merge(
+                    iter.filter_map(|(this, cfgs)| match this {
+                        Self::Unnamed(this) => Some((this, cfgs)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+                )?,
+            ),
+            Self::Unit => Self::Unit,
+        })
+    }
+
+    fn add_attr(
+        &mut self,
+        attr: crate::Attribute,
+        cx: &crate::MergeContext<'_>,
+    ) -> Result<(), crate::Error> {
         match self {
-            Self::Named(item) => item.add_attr(attr),
-            Self::Unnamed(item) => item.add_attr(attr),
-            Self::Unit => unimplemented!(),
-            _ => unimplemented!(),
+            Self::Named(item) => cx.add_attr(item, attr),
+            Self::Unnamed(item) => cx.add_attr(item, attr),
+            Self::Unit => Err(crate::Error::new(
+                crate::ErrorKind::Unsupported,
+                "can't add `#[cfg]` to unit fields",
+            )),
         }
     }
 }
 
 impl crate::Merge for syn::Stmt {
-    fn top_level_eq(&self, other: &Self) -> bool {
+    fn top_level_eq(&self, other: &Self, cx: &crate::MergeContext<'_>) -> bool {
         match (self, other) {
-            (Self::Local(this), Self::Local(other)) => this.top_level_eq(other),
-            (Self::Item(this), Self::Item(other)) => this.top_level_eq(other),
-            (Self::Expr(this, _), Self::Expr(other, _)) => this.top_level_eq(other),
-            (Self::Macro(this), Self::Macro(other)) => this.top_level_eq(other),
+            (Self::Local(this), Self::Local(other)) => cx.top_level_eq(this, other),
+            (Self::Item(this), Self::Item(other)) => cx.top_level_eq(this, other),
+            (Self::Expr(this, _), Self::Expr(other, _)) => cx.top_level_eq(this, other),
+            (Self::Macro(this), Self::Macro(other)) => cx.top_level_eq(this, other),
             _ => false,
         }
     }
 
-    fn add_attr(&mut self, attr: crate::Attribute) {
+    fn merge<'a, I: IntoIterator<Item = (&'a Self, &'a crate::Cfgs)>>(
+        iter: I,
+        cx: &crate::MergeContext<'_>,
+    ) -> Result<Self, crate::Error>
+    where
+        Self: 'a,
+        I::IntoIter: Clone,
+    {
+        let iter = iter.into_iter();
+        let first = crate::first(iter.clone())?;
+        // Collected to avoid infinitely nested iterator types
+        Ok(match first {
+            Self::Local(_) => Self::Local(
+                cx.merge(
+                    iter.filter_map(|(this, cfgs)| match this {
+                        Self::Local(this) => Some((this, cfgs)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+                )?,
+            ),
+            Self::Item(_) => Self::Item(
+                cx.merge(
+                    iter.filter_map(|(this, cfgs)| match this {
+                        Self::Item(this) => Some((this, cfgs)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+                )?,
+            ),
+            Self::Expr(_, semi) => Self::Expr(
+                cx.merge(
+                    iter.filter_map(|(this, cfgs)| match this {
+                        Self::Expr(this, _) => Some((this, cfgs)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+                )?,
+                *semi,
+            ),
+            Self::Macro(_) => Self::Macro(
+                cx.merge(
+                    iter.filter_map(|(this, cfgs)| match this {
+                        Self::Macro(this) => Some((this, cfgs)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+                )?,
+            ),
+        })
+    }
+
+    fn add_attr(
+        &mut self,
+        attr: crate::Attribute,
+        cx: &crate::MergeContext<'_>,
+    ) -> Result<(), crate::Error> {
+        match self {
+            Self::Local(item) => cx.add_attr(item, attr),
+            Self::Item(item) => cx.add_attr(item, attr),
+            Self::Expr(item, _) => cx.add_attr(item, attr),
+            Self::Macro(item) => cx.add_attr(item, attr),
+        }
+    }
+
+    fn attrs(&self) -> &[crate::Attribute] {
+        match self {
+            Self::Local(item) => item.attrs(),
+            Self::Item(item) => item.attrs(),
+            Self::Expr(item, _) => item.attrs(),
+            Self::Macro(item) => item.attrs(),
+        }
+    }
+
+    fn attrs_mut(&mut self) -> Option<&mut Vec<crate::Attribute>> {
         match self {
-            Self::Local(item) => item.add_attr(attr),
-            Self::Item(item) => item.add_attr(attr),
-            Self::Expr(item, _) => item.add_attr(attr),
-            Self::Macro(item) => item.add_attr(attr),
-            _ => unimplemented!(),
+            Self::Local(item) => item.attrs_mut(),
+            Self::Item(item) => item.attrs_mut(),
+            Self::Expr(item, _) => item.attrs_mut(),
+            Self::Macro(item) => item.attrs_mut(),
         }
     }
 }
@@ -191,7 +191,7 @@ impl_merge_struct! {
     colon_token, ty, eq_token, expr, semi_token, }
 }
 impl_merge_struct! {
-    #[attrs] syn::ImplItemFn { attrs, vis, defaultness, sig, block, }
+    #[attrs] syn::ImplItemFn { attrs, vis, defaultness, sig, #[eq] block, }
 }
 impl_merge_struct! {
     #[attrs] syn::ImplItemMacro { attrs, mac, semi_token, }
@@ -213,7 +213,7 @@ impl_merge_struct! {
     semi_token, }
 }
 impl_merge_struct! {
-    #[attrs] syn::ItemFn { attrs, vis, sig, block, }
+    #[attrs] syn::ItemFn { attrs, vis, sig, #[eq] block, }
 }
 impl_merge_struct! {
     #[attrs] syn::ItemForeignMod { attrs, unsafety, abi, brace_token, items, }
@@ -314,7 +314,7 @@ impl_merge_struct! {
     default, semi_token, }
 }
 impl_merge_struct! {
-    #[attrs] syn::TraitItemFn { attrs, sig, default, semi_token, }
+    #[attrs] syn::TraitItemFn { attrs, sig, #[eq] default, semi_token, }
 }
 impl_merge_struct! {
     #[attrs] syn::TraitItemMacro { attrs, mac, semi_token, }
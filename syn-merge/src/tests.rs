@@ -1,11 +1,9 @@
 use crate::*;
 use proc_macro2::TokenStream;
-use quote::quote;
+use quote::{quote, ToTokens};
 
-fn assert_merged(files: &[(TokenStream, &str)], expected: TokenStream) {
-    let expected = syn::parse_file(&expected.to_string()).unwrap();
-
-    let files: Vec<_> = files
+fn parse_files(files: &[(TokenStream, &str)]) -> Vec<(File, Cfgs)> {
+    files
         .iter()
         .map(|(tokens, cfgs)| {
             (
@@ -13,13 +11,21 @@ fn assert_merged(files: &[(TokenStream, &str)], expected: TokenStream) {
                 Cfgs::new(cfgs),
             )
         })
-        .collect();
-    let actual = merge_files(&files).unwrap();
-    if expected != actual {
+        .collect()
+}
+
+fn assert_merged(files: &[(TokenStream, &str)], expected: TokenStream) {
+    let actual = merge_files(&parse_files(files)).unwrap();
+    assert_files_eq(&actual, expected);
+}
+
+fn assert_files_eq(actual: &File, expected: TokenStream) {
+    let expected = syn::parse_file(&expected.to_string()).unwrap();
+    if expected != *actual {
         panic!(
             "expected:\n\n{}\n-------\nactual:\n\n{}",
             prettyplease::unparse(&expected),
-            prettyplease::unparse(&actual),
+            prettyplease::unparse(actual),
         );
     }
 }
@@ -174,3 +180,2222 @@ fn many_items() {
         },
     );
 }
+
+#[test]
+fn cfg_any() {
+    assert_merged(
+        files_with_cfg! {
+            #[cfg(a)]
+            mod _ {
+                fn shared() {}
+                fn only_ac() {}
+                fn only_a() {}
+            }
+
+            #[cfg(b)]
+            mod _ {
+                fn shared() {}
+                fn only_b() {}
+            }
+
+            #[cfg(c)]
+            mod _ {
+                fn shared() {}
+                fn only_ac() {}
+            }
+        },
+        quote! {
+            fn shared() {}
+            #[cfg(any(a, c))]
+            fn only_ac() {}
+            #[cfg(a)]
+            fn only_a() {}
+            #[cfg(b)]
+            fn only_b() {}
+        },
+    );
+}
+
+#[test]
+fn hoist_into_submodules() {
+    let files = files_with_cfg! {
+        #[cfg(foo)]
+        mod _ {
+            pub struct Shared;
+            struct Foo(u8);
+            impl Foo {
+                fn new() -> Self { Self(0) }
+            }
+            pub fn foo() {}
+        }
+
+        #[cfg(bar)]
+        mod _ {
+            pub struct Shared;
+            pub fn bar() {}
+        }
+    };
+    let merged = merge_files(&parse_files(files)).unwrap();
+
+    assert_files_eq(
+        &crate::hoist_into_submodules(merged),
+        quote! {
+            pub struct Shared;
+            #[cfg(foo)]
+            mod __foo {
+                use super::*;
+                pub(super) struct Foo(pub(super) u8);
+                impl Foo {
+                    pub(super) fn new() -> Self { Self(0) }
+                }
+                pub fn foo() {}
+            }
+            #[cfg(foo)]
+            pub use __foo::*;
+            #[cfg(bar)]
+            mod __bar {
+                use super::*;
+                pub fn bar() {}
+            }
+            #[cfg(bar)]
+            pub use __bar::*;
+        },
+    );
+}
+
+#[test]
+fn hoist_nested_modules() {
+    let file = syn::parse_quote! {
+        pub mod sys {
+            pub fn shared() {}
+            #[cfg(foo)]
+            pub fn foo() {}
+            #[cfg(bar)]
+            mod inner {
+                #[cfg(bar)]
+                fn bar() {}
+            }
+        }
+        #[cfg(foo)]
+        pub fn foo() {}
+    };
+
+    assert_files_eq(
+        &crate::hoist_into_submodules(file),
+        quote! {
+            pub mod sys {
+                pub fn shared() {}
+                #[cfg(bar)]
+                mod inner {
+                    #[cfg(bar)]
+                    mod __bar {
+                        use super::*;
+                        pub(super) fn bar() {}
+                    }
+                    #[cfg(bar)]
+                    pub use __bar::*;
+                }
+                #[cfg(foo)]
+                mod __foo {
+                    use super::*;
+                    pub fn foo() {}
+                }
+                #[cfg(foo)]
+                pub use __foo::*;
+            }
+            #[cfg(foo)]
+            mod __foo {
+                use super::*;
+                pub fn foo() {}
+            }
+            #[cfg(foo)]
+            pub use __foo::*;
+        },
+    );
+}
+
+#[test]
+fn hoist_name_collisions() {
+    let file = syn::parse_quote! {
+        mod __foo {}
+        use self::other::__foo_2;
+        #[cfg(foo)]
+        fn foo() {}
+    };
+
+    assert_files_eq(
+        &crate::hoist_into_submodules(file),
+        quote! {
+            mod __foo {}
+            use self::other::__foo_2;
+            #[cfg(foo)]
+            mod __foo_3 {
+                use super::*;
+                pub(super) fn foo() {}
+            }
+            #[cfg(foo)]
+            pub use __foo_3::*;
+        },
+    );
+}
+
+#[test]
+fn hoist_visibility() {
+    let file = syn::parse_quote! {
+        #[cfg(foo)]
+        pub(crate) struct Crate {
+            pub(self) a: u8,
+            pub(super) b: u8,
+            pub(in crate::sys) c: u8,
+            pub(in self::inner) d: u8,
+            pub(in super::inner) e: u8,
+        }
+        #[cfg(foo)]
+        impl Crate {
+            pub const A: u8 = 0;
+            const B: u8 = 0;
+            type C = u8;
+        }
+        #[cfg(foo)]
+        impl Default for Crate {
+            fn default() -> Self {
+                todo!()
+            }
+        }
+        #[cfg(foo)]
+        extern "C" {
+            fn ext();
+            static EXT: u8;
+        }
+        #[cfg(foo)]
+        union U {
+            pub a: u8,
+            b: u16,
+        }
+    };
+
+    assert_files_eq(
+        &crate::hoist_into_submodules(file),
+        quote! {
+            #[cfg(foo)]
+            mod __foo {
+                use super::*;
+                pub(crate) struct Crate {
+                    pub(in super) a: u8,
+                    pub(in super::super) b: u8,
+                    pub(in crate::sys) c: u8,
+                    pub(in super::inner) d: u8,
+                    pub(in super::super::inner) e: u8,
+                }
+                impl Crate {
+                    pub const A: u8 = 0;
+                    pub(super) const B: u8 = 0;
+                    pub(super) type C = u8;
+                }
+                impl Default for Crate {
+                    fn default() -> Self {
+                        todo!()
+                    }
+                }
+                extern "C" {
+                    pub(super) fn ext();
+                    pub(super) static EXT: u8;
+                }
+                pub(super) union U {
+                    pub a: u8,
+                    pub(super) b: u16,
+                }
+            }
+            #[cfg(foo)]
+            pub use __foo::*;
+        },
+    );
+}
+
+#[test]
+fn simplify_nested_cfgs() {
+    let file = syn::parse_quote! {
+        #[cfg(unix)]
+        mod sys {
+            #[cfg(all(unix, linux))]
+            fn linux() {}
+            #[cfg(unix)]
+            fn unix() {}
+            #[cfg(any(unix, windows))]
+            #[cfg(macos)]
+            fn macos() {}
+        }
+
+        #[cfg(a)]
+        impl Foo {
+            #[cfg(all(a, b))]
+            fn foo() {
+                #[cfg(any(a, b))]
+                let x = 5;
+                #[cfg(all(b, c))]
+                let y = 5;
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        extern "C" {
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            fn shared();
+            #[cfg(not(target_os = "macos"))]
+            fn not_macos();
+        }
+    };
+
+    assert_files_eq(
+        &crate::simplify_nested_cfgs(file),
+        quote! {
+            #[cfg(unix)]
+            mod sys {
+                #[cfg(linux)]
+                fn linux() {}
+                fn unix() {}
+                #[cfg(macos)]
+                fn macos() {}
+            }
+
+            #[cfg(a)]
+            impl Foo {
+                #[cfg(b)]
+                fn foo() {
+                    let x = 5;
+                    #[cfg(c)]
+                    let y = 5;
+                }
+            }
+
+            #[cfg(target_os = "linux")]
+            extern "C" {
+                fn shared();
+                fn not_macos();
+            }
+        },
+    );
+}
+
+#[test]
+fn incremental_merge() {
+    let files = parse_files(files_with_cfg! {
+        #[cfg(a)]
+        mod _ {
+            mod m {
+                fn x() {}
+                fn y() {}
+            }
+            fn only_ab() {}
+        }
+
+        #[cfg(b)]
+        mod _ {
+            mod m {
+                fn x() {}
+            }
+            fn only_ab() {}
+        }
+
+        #[cfg(c)]
+        mod _ {
+            mod m {
+                fn y() {}
+                fn z() {}
+            }
+        }
+    });
+
+    let expected = quote! {
+        mod m {
+            #[cfg(any(a, b))]
+            fn x() {}
+            #[cfg(any(a, c))]
+            fn y() {}
+            #[cfg(c)]
+            fn z() {}
+        }
+        #[cfg(any(a, b))]
+        fn only_ab() {}
+    };
+    assert_files_eq(&merge_files(&files).unwrap(), expected.clone());
+
+    let merged = merge_files(&files[..2]).unwrap();
+    let configs = [files[0].1.clone(), files[1].1.clone()];
+    let actual = merge_into(&merged, &configs, &files[2]).unwrap();
+    assert_files_eq(&actual, expected);
+}
+
+#[test]
+fn incremental_merge_keeps_own_cfgs() {
+    let files = parse_files(files_with_cfg! {
+        #[cfg(a)]
+        mod _ {
+            #[cfg(feature = "x")]
+            fn x() {}
+            fn s() {}
+        }
+
+        #[cfg(b)]
+        mod _ {
+            fn s() {}
+        }
+
+        #[cfg(c)]
+        mod _ {
+            fn s() {}
+        }
+    });
+
+    let full = merge_files(&files).unwrap();
+    assert_files_eq(
+        &full,
+        quote! {
+            #[cfg(all(a, feature = "x"))]
+            fn x() {}
+            fn s() {}
+        },
+    );
+
+    let merged = merge_files(&files[..2]).unwrap();
+    let configs = [files[0].1.clone(), files[1].1.clone()];
+    let actual = merge_into(&merged, &configs, &files[2]).unwrap();
+    assert_eq!(actual, full);
+}
+
+#[test]
+fn struct_fields() {
+    assert_merged(
+        files_with_cfg! {
+            #[cfg(foo)]
+            mod _ {
+                #[derive(Debug)]
+                #[repr(C)]
+                struct Foo {
+                    a: u8,
+                    foo: u16,
+                }
+            }
+
+            #[cfg(bar)]
+            mod _ {
+                #[derive(Debug)]
+                struct Foo {
+                    a: u8,
+                    bar: u32,
+                }
+            }
+        },
+        quote! {
+            #[derive(Debug)]
+            #[cfg_attr(foo, repr(C))]
+            struct Foo {
+                a: u8,
+                #[cfg(foo)]
+                foo: u16,
+                #[cfg(bar)]
+                bar: u32,
+            }
+        },
+    );
+}
+
+#[test]
+fn differing_attributes() {
+    assert_merged(
+        files_with_cfg! {
+            #[cfg(a)]
+            mod _ {
+                #[derive(Debug)]
+                #[repr(u8)]
+                #[non_exhaustive]
+                enum E {}
+            }
+
+            #[cfg(b)]
+            mod _ {
+                #[derive(Debug)]
+                #[repr(u16)]
+                #[non_exhaustive]
+                enum E {}
+            }
+
+            #[cfg(c)]
+            mod _ {
+                #[derive(Debug)]
+                #[repr(u8)]
+                #[must_use]
+                enum E {}
+            }
+        },
+        quote! {
+            #[derive(Debug)]
+            #[cfg_attr(any(a, c), repr(u8))]
+            #[cfg_attr(b, repr(u16))]
+            #[cfg_attr(any(a, b), non_exhaustive)]
+            #[cfg_attr(c, must_use)]
+            enum E {}
+        },
+    );
+}
+
+#[test]
+fn nested_items() {
+    assert_merged(
+        files_with_cfg! {
+            #[cfg(foo)]
+            mod _ {
+                enum E {
+                    A(u8),
+                    Foo,
+                }
+                mod m {
+                    fn foo() {}
+                }
+                impl Trait for E {
+                    fn shared() {}
+                    fn foo() {}
+                }
+            }
+
+            #[cfg(bar)]
+            mod _ {
+                enum E {
+                    A(u8, #[cfg(x)] u16),
+                    Bar { x: u8, },
+                }
+                mod m {
+                    fn bar() {}
+                }
+                impl Trait for E {
+                    fn shared() {}
+                }
+            }
+        },
+        quote! {
+            enum E {
+                A(u8, #[cfg(all(bar, x))] u16),
+                #[cfg(foo)]
+                Foo,
+                #[cfg(bar)]
+                Bar { x: u8, },
+            }
+            mod m {
+                #[cfg(foo)]
+                fn foo() {}
+                #[cfg(bar)]
+                fn bar() {}
+            }
+            impl Trait for E {
+                fn shared() {}
+                #[cfg(foo)]
+                fn foo() {}
+            }
+        },
+    );
+
+    let stmts: Vec<syn::Stmt> = crate::merge(&[
+        (syn::parse_quote!(let a = 1; let b = 2;), Cfgs::new("foo")),
+        (syn::parse_quote!(let a = 1;), Cfgs::new("bar")),
+    ])
+    .unwrap();
+    let expected: Vec<syn::Stmt> = syn::parse_quote! {
+        let a = 1;
+        #[cfg(foo)]
+        let b = 2;
+    };
+    assert_eq!(stmts, expected);
+}
+
+#[test]
+fn existing_cfgs() {
+    assert_merged(
+        files_with_cfg! {
+            #[cfg(foo)]
+            mod _ {
+                #[cfg(debug_assertions)]
+                fn debug() {}
+                fn shared() {}
+                #[cfg(feature = "x")]
+                fn x() {}
+                #[cfg(feature = "y")]
+                fn y() {}
+            }
+
+            #[cfg(bar)]
+            mod _ {
+                #[cfg(debug_assertions)]
+                fn debug() {}
+                fn shared() {}
+                fn y() {}
+            }
+        },
+        quote! {
+            #[cfg(debug_assertions)]
+            fn debug() {}
+            fn shared() {}
+            #[cfg(all(foo, feature = "x"))]
+            fn x() {}
+            #[cfg(any(all(foo, feature = "y"), bar))]
+            fn y() {}
+        },
+    );
+}
+
+#[test]
+fn existing_unparsable_cfgs() {
+    assert_merged(
+        files_with_cfg! {
+            #[cfg(foo)]
+            mod _ {
+                #[cfg(feature = "f")]
+                #[cfg(accessible(::a))]
+                fn x() {}
+            }
+
+            #[cfg(bar)]
+            mod _ {
+                #[cfg(feature = "g")]
+                fn x() {}
+            }
+        },
+        // The `cfg`s of the first input can't be combined, and are kept
+        quote! {
+            #[cfg_attr(foo, cfg(feature = "f"))]
+            #[cfg_attr(foo, cfg(accessible(::a)))]
+            #[cfg(any(foo, all(bar, feature = "g")))]
+            fn x() {}
+        },
+    );
+}
+
+#[test]
+fn specialize() {
+    let file = syn::parse_quote! {
+        #[cfg(foo)]
+        fn a() {}
+        #[cfg_attr(foo, derive(Debug))]
+        #[cfg_attr(bar, repr(C))]
+        #[cfg_attr(foo, cfg_attr(not(bar), derive(Clone), derive(Copy)))]
+        struct S {
+            #[cfg(bar)]
+            x: u8,
+            y: u8,
+        }
+        fn f() -> bool {
+            cfg!(any(foo, bar)) && cfg!(bar)
+        }
+    };
+
+    assert_files_eq(
+        &crate::specialize(&file, &Cfgs::new("foo")),
+        quote! {
+            fn a() {}
+            #[derive(Debug)]
+            #[derive(Clone)]
+            #[derive(Copy)]
+            struct S {
+                y: u8,
+            }
+            fn f() -> bool {
+                true && false
+            }
+        },
+    );
+}
+
+#[test]
+fn specialize_round_trip() {
+    let files = parse_files(files_with_cfg! {
+        #[cfg(foo)]
+        mod _ {
+            #[repr(C)]
+            struct Foo {
+                a: u8,
+                foo: u16,
+            }
+            fn foo() {}
+        }
+
+        #[cfg(bar)]
+        mod _ {
+            struct Foo {
+                a: u8,
+                bar: u32,
+            }
+            fn bar() {}
+        }
+    });
+    let merged = merge_files(&files).unwrap();
+    for (file, cfgs) in &files {
+        assert_eq!(crate::specialize(&merged, cfgs), *file);
+    }
+}
+
+#[test]
+fn verify() {
+    let files = parse_files(files_with_cfg! {
+        #[cfg(foo)]
+        mod _ {
+            #[cfg(feature = "x")]
+            fn x() {}
+            mod sys {
+                fn shared() {}
+                fn foo() {}
+            }
+        }
+
+        #[cfg(bar)]
+        mod _ {
+            mod sys {
+                fn shared() {}
+            }
+        }
+    });
+    let merged = merge_files(&files).unwrap();
+    crate::verify(&files, &merged, &Normalization::new()).unwrap();
+
+    let broken = syn::parse_quote! {
+        #[cfg(all(foo, feature = "x"))]
+        fn x() {}
+        mod sys {
+            fn shared() {}
+            #[cfg(bar)]
+            fn foo() {}
+        }
+    };
+    let err = crate::verify(&files, &broken, &Normalization::new()).unwrap_err();
+    assert_eq!(err.input, 0);
+    assert_eq!(err.path, ["mod sys", "fn foo"]);
+    assert_eq!(err.expected.as_deref(), Some("fn foo () { }"));
+    assert_eq!(err.actual, None);
+    assert_eq!(
+        err.to_string(),
+        "input 0 differs at `mod sys > fn foo`: missing `fn foo () { }`",
+    );
+
+    // A missing item is reported as such, not as a difference from the next
+    let broken = syn::parse_quote! {
+        #[cfg(all(foo, feature = "x"))]
+        fn x() {}
+        mod sys {
+            #[cfg(foo)]
+            fn foo() {}
+        }
+    };
+    let err = crate::verify(&files, &broken, &Normalization::new()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "input 0 differs at `mod sys > fn shared`: missing `fn shared () { }`",
+    );
+
+    // Items with `cfg`s of their own are compared too
+    let broken = syn::parse_quote! {
+        mod sys {
+            fn shared() {}
+            #[cfg(foo)]
+            fn foo() {}
+        }
+    };
+    let err = crate::verify(&files, &broken, &Normalization::new()).unwrap_err();
+    assert_eq!(err.input, 0);
+    assert_eq!(err.path, ["fn x"]);
+    assert_eq!(
+        err.expected.as_deref(),
+        Some("# [cfg (feature = \"x\")] fn x () { }")
+    );
+}
+
+#[test]
+fn check_overlaps() {
+    let configs = [Cfgs::new("foo"), Cfgs::new("bar")];
+    let merged: File = syn::parse_quote! {
+        #[cfg(any(foo, bar))]
+        fn a() {}
+        #[cfg(foo)]
+        fn a() {}
+
+        #[cfg(foo)]
+        fn b() {}
+        #[cfg(bar)]
+        fn b() {}
+
+        struct C;
+        fn C() {}
+
+        mod m {
+            #[cfg(bar)]
+            struct D {}
+            #[cfg(not(foo))]
+            enum D {}
+        }
+    };
+    let overlaps = crate::check_overlaps(&merged, &configs);
+    assert_eq!(
+        overlaps,
+        [
+            Overlap {
+                scope: vec![],
+                namespace: Namespace::Value,
+                name: "a".into(),
+                items: (0, 1),
+                configs: vec![0],
+            },
+            Overlap {
+                scope: vec![],
+                namespace: Namespace::Value,
+                name: "C".into(),
+                items: (4, 5),
+                configs: vec![0, 1],
+            },
+            Overlap {
+                scope: vec!["mod m".into()],
+                namespace: Namespace::Type,
+                name: "D".into(),
+                items: (0, 1),
+                configs: vec![1],
+            },
+        ]
+    );
+    assert_eq!(
+        overlaps[2].to_string(),
+        "`D` is defined twice in the type namespace of `mod m` (items 0 and 1) in configurations [1]",
+    );
+
+    // Inherent impls of the same type share a scope, and trait impls can
+    // overlap themselves
+    let merged: File = syn::parse_quote! {
+        struct Foo;
+        impl Foo {
+            fn a() {}
+            #[cfg(foo)]
+            fn b() {}
+        }
+        impl Foo {
+            fn a() {}
+            #[cfg(bar)]
+            fn b() {}
+        }
+        #[cfg(foo)]
+        impl Clone for Foo {
+            fn clone(&self) -> Self { Foo }
+        }
+        impl Clone for Foo {
+            fn clone(&self) -> Self { Foo }
+        }
+        #[cfg(bar)]
+        impl Clone for Bar {}
+    };
+    let overlaps = crate::check_overlaps(&merged, &configs);
+    assert_eq!(
+        overlaps,
+        [
+            Overlap {
+                scope: vec![],
+                namespace: Namespace::Impl,
+                name: "impl Clone for Foo".into(),
+                items: (3, 4),
+                configs: vec![0],
+            },
+            Overlap {
+                scope: vec!["impl Foo".into()],
+                namespace: Namespace::Value,
+                name: "a".into(),
+                items: (0, 2),
+                configs: vec![0, 1],
+            },
+        ]
+    );
+    assert_eq!(
+        overlaps[0].to_string(),
+        "`impl Clone for Foo` is defined twice in the impl namespace (items 3 and 4) in configurations [0]",
+    );
+
+    let merged: File = syn::parse_quote! {
+        macro_rules! m {
+            () => {};
+        }
+        #[cfg(foo)]
+        macro_rules! m {
+            () => {};
+        }
+    };
+    assert_eq!(crate::check_overlaps(&merged, &configs), []);
+
+    let files = parse_files(files_with_cfg! {
+        #[cfg(foo)]
+        mod _ {
+            fn shared() {}
+            fn differs() -> u8 { 1 }
+        }
+
+        #[cfg(bar)]
+        mod _ {
+            fn shared() {}
+            fn differs() -> u16 { 1 }
+        }
+    });
+    let merged = merge_files(&files).unwrap();
+    assert_eq!(crate::check_overlaps(&merged, &configs), []);
+}
+
+#[test]
+fn compile_error_for_unsupported() {
+    let files = parse_files(files_with_cfg! {
+        #[cfg(unix)]
+        mod _ {
+            fn shared() {}
+            fn unix() {}
+        }
+
+        #[cfg(windows)]
+        mod _ {
+            fn shared() {}
+        }
+    });
+
+    let options = MergeOptions::new().compile_error("unsupported target");
+    let merged = merge_files_with_options(&files, &options).unwrap();
+    assert_files_eq(
+        &merged,
+        quote! {
+            fn shared() {}
+            #[cfg(unix)]
+            fn unix() {}
+            #[cfg(not(any(unix, windows)))]
+            compile_error!("unsupported target");
+        },
+    );
+
+    // Nothing is unsupported if the inputs cover the universe
+    let options = options.universe(syn::parse_quote!(any(unix, windows)));
+    let merged = merge_files_with_options(&files, &options).unwrap();
+    assert_files_eq(
+        &merged,
+        quote! {
+            fn shared() {}
+            #[cfg(unix)]
+            fn unix() {}
+        },
+    );
+}
+
+#[test]
+fn minimize() {
+    let files = parse_files(files_with_cfg! {
+        #[cfg(foo)]
+        mod _ {
+            mod sys {
+                #[cfg(foo)]
+                fn a() {}
+            }
+        }
+
+        #[cfg(bar)]
+        mod _ {}
+    });
+    let merged = merge_files(&files).unwrap();
+    assert_files_eq(
+        &merged,
+        quote! {
+            #[cfg(foo)]
+            mod sys {
+                #[cfg(foo)]
+                fn a() {}
+            }
+        },
+    );
+    let options = MergeOptions::new().minimize(true);
+    let merged = merge_files_with_options(&files, &options).unwrap();
+    assert_files_eq(
+        &merged,
+        quote! {
+            #[cfg(foo)]
+            mod sys {
+                fn a() {}
+            }
+        },
+    );
+}
+
+#[test]
+fn check_cfg() {
+    let file: File = syn::parse_quote! {
+        #[cfg(any(foo, all(unix, target_os = "linux")))]
+        fn a() {
+            if cfg!(not(bar)) {}
+        }
+
+        #[cfg_attr(feature = "x", cfg_attr(kind = "big", derive(Debug)))]
+        #[cfg(any(kind = "small", kind))]
+        struct B;
+    };
+    let check_cfg = CheckCfg::from_file(&file);
+    assert_eq!(
+        check_cfg.names().collect::<Vec<_>>(),
+        ["bar", "feature", "foo", "kind", "target_os", "unix"],
+    );
+    assert_eq!(
+        check_cfg.values("kind").collect::<Vec<_>>(),
+        [None, Some("big"), Some("small")],
+    );
+    assert_eq!(
+        check_cfg.build_script_lines(),
+        [
+            "cargo::rustc-check-cfg=cfg(bar)",
+            "cargo::rustc-check-cfg=cfg(foo)",
+            "cargo::rustc-check-cfg=cfg(kind, values(none(), \"big\", \"small\"))",
+        ],
+    );
+}
+
+#[test]
+fn cfg_aliases() {
+    let file: File = syn::parse_quote! {
+        #[cfg(any(all(target_os = "linux", target_arch = "x86_64"), target_os = "android"))]
+        fn a() {
+            if cfg!(any(all(target_os = "linux", target_arch = "x86_64"), target_os = "android")) {}
+        }
+        #[cfg(any(all(target_os = "linux", target_arch = "x86_64"), target_os = "android"))]
+        fn b() {}
+        #[cfg(not(windows))]
+        fn c() {}
+        #[cfg(unix)]
+        fn d() {}
+        #[cfg(unix)]
+        fn e() {}
+        #[cfg_attr(any(all(target_os = "linux", target_arch = "x86_64"), target_os = "android"), derive(Debug))]
+        struct F;
+        #[cfg_attr(unix, cfg_attr(any(all(target_os = "linux", target_arch = "x86_64"), target_os = "android"), inline))]
+        fn g() {}
+    };
+    let aliases = CfgAliases::find(&file, 2);
+    assert_eq!(
+        aliases
+            .iter()
+            .map(|(name, predicate)| (name, predicate.to_string()))
+            .collect::<Vec<_>>(),
+        [(
+            "linux_x86_64_android",
+            r#"any(all(target_os = "linux", target_arch = "x86_64"), target_os = "android")"#
+                .to_string(),
+        )],
+    );
+    assert_files_eq(
+        &aliases.apply(file),
+        quote! {
+            #[cfg(linux_x86_64_android)]
+            fn a() {
+                if cfg!(linux_x86_64_android) {}
+            }
+            #[cfg(linux_x86_64_android)]
+            fn b() {}
+            #[cfg(not(windows))]
+            fn c() {}
+            #[cfg(unix)]
+            fn d() {}
+            #[cfg(unix)]
+            fn e() {}
+            #[cfg_attr(linux_x86_64_android, derive(Debug))]
+            struct F;
+            #[cfg_attr(unix, cfg_attr(linux_x86_64_android, inline))]
+            fn g() {}
+        },
+    );
+
+    let aliases = CfgAliases::new().alias("not_windows", syn::parse_quote!(not(windows)));
+    let build_script = aliases.build_script();
+    assert_files_eq(
+        &syn::parse_quote!(
+            fn main() {
+                #build_script
+            }
+        ),
+        quote! {
+            fn main() {
+                let cfg = |key: &str, value: ::core::option::Option<&str>| -> bool {
+                    let var = ::std::format!("CARGO_CFG_{}", key.to_uppercase());
+                    match (::std::env::var(var), value) {
+                        (::core::result::Result::Ok(_), ::core::option::Option::None) => true,
+                        (::core::result::Result::Ok(values), ::core::option::Option::Some(value)) => {
+                            values.split(',').any(|v| v == value)
+                        }
+                        (::core::result::Result::Err(_), _) => false,
+                    }
+                };
+                println!("cargo:rustc-check-cfg=cfg(not_windows)");
+                if !cfg("windows", ::core::option::Option::None) {
+                    println!("cargo:rustc-cfg=not_windows");
+                }
+            }
+        },
+    );
+}
+
+#[test]
+fn doc_cfg() {
+    let files = parse_files(files_with_cfg! {
+        #[cfg(foo)]
+        mod _ {
+            pub fn shared() {}
+            pub fn public() {}
+            fn private() {}
+            pub struct S;
+            impl S {
+                pub fn method() {}
+            }
+        }
+
+        #[cfg(bar)]
+        mod _ {
+            pub fn shared() {}
+            pub struct S;
+        }
+    });
+    let options = MergeOptions::new().doc_cfg(true);
+    let merged = merge_files_with_options(&files, &options).unwrap();
+    assert_files_eq(
+        &merged,
+        quote! {
+            pub fn shared() {}
+            #[cfg(foo)]
+            #[cfg_attr(docsrs, doc(cfg(foo)))]
+            pub fn public() {}
+            #[cfg(foo)]
+            fn private() {}
+            pub struct S;
+            #[cfg(foo)]
+            impl S {
+                pub fn method() {}
+            }
+        },
+    );
+}
+
+#[test]
+fn doc_comments() {
+    let files = parse_files(&[
+        (
+            quote! {
+                /// Shared.
+                /// On foo.
+                #[doc = "Block\nfoo only\nend"]
+                fn a() {}
+            },
+            "foo",
+        ),
+        (
+            quote! {
+                /// Shared.
+                /// On bar.
+                #[doc = "Block\nbar only\nend"]
+                fn a() {}
+            },
+            "bar",
+        ),
+    ]);
+    assert_files_eq(
+        &merge_files(&files).unwrap(),
+        quote! {
+            /// Shared.
+            #[cfg_attr(foo, doc = r" On foo.")]
+            #[cfg_attr(bar, doc = r" On bar.")]
+            #[doc = "Block"]
+            #[cfg_attr(foo, doc = "foo only")]
+            #[cfg_attr(bar, doc = "bar only")]
+            #[doc = "end"]
+            fn a() {}
+        },
+    );
+}
+
+#[test]
+fn doc_comments_only_other_attrs_differ() {
+    let files = parse_files(&[
+        (
+            quote! {
+                #[doc = "Block\nshared\nend"]
+                #[inline]
+                fn a() {}
+            },
+            "foo",
+        ),
+        (
+            quote! {
+                #[doc = "Block\nshared\nend"]
+                fn a() {}
+            },
+            "bar",
+        ),
+    ]);
+    assert_files_eq(
+        &merge_files(&files).unwrap(),
+        quote! {
+            #[doc = "Block\nshared\nend"]
+            #[cfg_attr(foo, inline)]
+            fn a() {}
+        },
+    );
+}
+
+#[test]
+fn merge_fragments() {
+    let items: Vec<syn::Item> = crate::merge(&[
+        (
+            vec![
+                syn::parse_quote!(
+                    fn a() {}
+                ),
+                syn::parse_quote!(
+                    fn b() {}
+                ),
+            ],
+            Cfgs::new("foo"),
+        ),
+        (
+            vec![syn::parse_quote!(
+                fn a() {}
+            )],
+            Cfgs::new("bar"),
+        ),
+    ])
+    .unwrap();
+    assert_eq!(
+        items,
+        [
+            syn::parse_quote!(
+                fn a() {}
+            ),
+            syn::parse_quote!(
+                #[cfg(foo)]
+                fn b() {}
+            ),
+        ] as [syn::Item; 2],
+    );
+
+    let item: syn::ItemImpl = crate::merge(&[
+        (syn::parse_quote!(impl S { fn a() {} }), Cfgs::new("foo")),
+        (syn::parse_quote!(impl S { fn b() {} }), Cfgs::new("bar")),
+    ])
+    .unwrap();
+    assert_eq!(
+        item,
+        syn::parse_quote! {
+            impl S {
+                #[cfg(foo)]
+                fn a() {}
+                #[cfg(bar)]
+                fn b() {}
+            }
+        },
+    );
+
+    let block: syn::Block = crate::merge(&[
+        (
+            syn::parse_quote!({
+                let a = 1;
+                f(a);
+            }),
+            Cfgs::new("foo"),
+        ),
+        (
+            syn::parse_quote!({
+                let a = 1;
+                g(a);
+            }),
+            Cfgs::new("bar"),
+        ),
+    ])
+    .unwrap();
+    assert_eq!(
+        block,
+        syn::parse_quote!({
+            let a = 1;
+            #[cfg(foo)]
+            f(a);
+            #[cfg(bar)]
+            g(a);
+        }),
+    );
+
+    let err = crate::merge::<syn::Expr>(&[
+        (syn::parse_quote!(1 + 2), Cfgs::new("foo")),
+        (syn::parse_quote!(f()), Cfgs::new("bar")),
+    ])
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Mismatch);
+    assert_eq!(err.input(), Some(1));
+    assert_eq!(err.to_string(), "input 1: can't be merged with input 0");
+    assert_eq!(
+        err,
+        Error::new(ErrorKind::Mismatch, "can't be merged with input 0").with_input(1)
+    );
+    assert_eq!(
+        crate::merge::<syn::Expr>(&[]).unwrap_err().to_string(),
+        "no input"
+    );
+    assert_eq!(merge_files(&[]).unwrap_err().to_string(), "no input");
+}
+
+#[test]
+fn derive_merge() {
+    #[derive(Clone, Debug, PartialEq, syn_merge_derive::Merge)]
+    struct Node {
+        #[merge(attrs)]
+        attrs: Vec<syn::Attribute>,
+        #[merge(eq)]
+        name: String,
+        children: Vec<Child>,
+        #[merge(skip)]
+        source: usize,
+    }
+
+    #[derive(Clone, Debug, PartialEq, syn_merge_derive::Merge)]
+    enum Child {
+        Item(Box<syn::Item>),
+        Named {
+            #[merge(attrs)]
+            attrs: Vec<syn::Attribute>,
+            #[merge(eq)]
+            name: String,
+        },
+        Empty,
+    }
+
+    let node = |source, children| Node {
+        attrs: vec![],
+        name: "node".into(),
+        children,
+        source,
+    };
+    let named = |name: &str| Child::Named {
+        attrs: vec![],
+        name: name.into(),
+    };
+    let merged = crate::merge(&[
+        (
+            node(
+                0,
+                vec![
+                    Child::Item(Box::new(syn::parse_quote!(
+                        fn a() {}
+                    ))),
+                    named("x"),
+                ],
+            ),
+            Cfgs::new("foo"),
+        ),
+        (
+            node(
+                1,
+                vec![
+                    Child::Item(Box::new(syn::parse_quote!(
+                        fn a() {}
+                    ))),
+                    named("y"),
+                ],
+            ),
+            Cfgs::new("bar"),
+        ),
+    ])
+    .unwrap();
+    assert_eq!(
+        merged,
+        node(
+            0,
+            vec![
+                Child::Item(Box::new(syn::parse_quote!(
+                    fn a() {}
+                ))),
+                Child::Named {
+                    attrs: vec![syn::parse_quote!(#[cfg(foo)])],
+                    name: "x".into(),
+                },
+                Child::Named {
+                    attrs: vec![syn::parse_quote!(#[cfg(bar)])],
+                    name: "y".into(),
+                },
+            ],
+        ),
+    );
+
+    // Can't have attributes, so they must be in all inputs
+    let err = crate::merge::<Vec<Child>>(&[
+        (vec![Child::Empty], Cfgs::new("foo")),
+        (vec![], Cfgs::new("bar")),
+    ])
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert_eq!(
+        err.to_string(),
+        "input 0: can't add `#[cfg]` to `Child::Empty`"
+    );
+
+    #[derive(Clone, Debug, PartialEq, syn_merge_derive::Merge)]
+    struct Leaf(#[merge(eq)] u32);
+    let err = crate::merge::<Vec<Leaf>>(&[
+        (vec![Leaf(1)], Cfgs::new("foo")),
+        (vec![Leaf(2)], Cfgs::new("bar")),
+    ])
+    .unwrap_err();
+    assert_eq!(err.to_string(), "input 0: can't add `#[cfg]` to `Leaf`");
+}
+
+#[test]
+fn merge_callbacks() {
+    let files = parse_files(files_with_cfg! {
+        #[cfg(foo)]
+        mod _ {
+            #[repr(C)]
+            struct A {
+                x: u8,
+            }
+            struct B {
+                x: u8,
+            }
+            my_macro!(foo);
+        }
+
+        #[cfg(bar)]
+        mod _ {
+            #[repr(C)]
+            struct A {
+                y: u8,
+            }
+            struct B {
+                y: u8,
+            }
+            my_macro!(bar);
+        }
+    });
+
+    let repr_c = |s: &syn::ItemStruct| {
+        s.attrs.iter().any(|attr| {
+            attr.path().is_ident("repr")
+                && attr.parse_args::<syn::Ident>().is_ok_and(|arg| arg == "C")
+        })
+    };
+    let options = MergeOptions::new()
+        .top_level_eq_with(move |a: &syn::ItemStruct, b| {
+            if repr_c(a) || repr_c(b) {
+                Some(a == b)
+            } else {
+                None
+            }
+        })
+        .macro_top_level_eq_with("my_macro", |_, _| Some(true))
+        .merge_with(|values: &[(&syn::Macro, &Cfgs)]| {
+            let args = values.iter().map(|(mac, _)| &mac.tokens);
+            let mut mac = values[0].0.clone();
+            mac.tokens = quote!(#(#args)|*);
+            Some(mac)
+        });
+    let merged = merge_files_with_options(&files, &options).unwrap();
+    assert_files_eq(
+        &merged,
+        quote! {
+            #[repr(C)]
+            #[cfg(foo)]
+            struct A {
+                x: u8,
+            }
+            #[repr(C)]
+            #[cfg(bar)]
+            struct A {
+                y: u8,
+            }
+            struct B {
+                #[cfg(foo)]
+                x: u8,
+                #[cfg(bar)]
+                y: u8,
+            }
+            my_macro!(foo | bar);
+        },
+    );
+
+    let options = MergeOptions::new().add_attr_with(|field: &mut syn::Field, attr| {
+        field.attrs.push(attr.clone());
+        field
+            .attrs
+            .push(syn::parse_quote!(#[doc = "Not always available"]));
+        true
+    });
+    let merged = merge_files_with_options(&files, &options).unwrap();
+    let b = merged
+        .items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Struct(item) if item.ident == "B" => Some(item),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(
+        b.fields.to_token_stream().to_string(),
+        quote!({
+            #[cfg(foo)]
+            #[doc = "Not always available"]
+            x: u8,
+            #[cfg(bar)]
+            #[doc = "Not always available"]
+            y: u8,
+        })
+        .to_string(),
+    );
+}
+
+#[test]
+fn unsupported_nodes() {
+    let verbatim = File {
+        shebang: None,
+        attrs: vec![],
+        items: vec![syn::Item::Verbatim(quote!(some tokens))],
+    };
+    let input = [
+        (
+            syn::parse_quote!(
+                fn a() {}
+            ),
+            Cfgs::new("foo"),
+        ),
+        (verbatim, Cfgs::new("bar")),
+    ];
+    let err = merge_files(&input).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert_eq!(err.input(), Some(1));
+    assert!(err.span().is_some());
+    assert_eq!(
+        err.to_string(),
+        "input 1: can't add `#[cfg]` to verbatim tokens"
+    );
+
+    // Inputs are told apart even if their configurations are equal
+    let input = [
+        (input[0].0.clone(), Cfgs::new("foo")),
+        (input[1].0.clone(), Cfgs::new("foo")),
+    ];
+    assert_eq!(merge_files(&input).unwrap_err().input(), Some(1));
+
+    // Unit fields are fine as long as they don't need a `#[cfg]`
+    crate::merge::<syn::Fields>(&[
+        (syn::Fields::Unit, Cfgs::new("foo")),
+        (syn::Fields::Unit, Cfgs::new("bar")),
+    ])
+    .unwrap();
+    let err = crate::merge::<Vec<syn::Fields>>(&[
+        (vec![syn::Fields::Unit], Cfgs::new("foo")),
+        (vec![], Cfgs::new("bar")),
+    ])
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert_eq!(err.input(), Some(0));
+
+    // Structs without attributes can't be guarded either
+    let err = crate::merge::<Vec<syn::Abi>>(&[
+        (vec![syn::parse_quote!(extern "C")], Cfgs::new("foo")),
+        (vec![], Cfgs::new("bar")),
+    ])
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert_eq!(err.to_string(), "input 0: can't add `#[cfg]` to `syn::Abi`");
+}
+
+#[test]
+fn verbatim() {
+    // Verbatim items, impl items and expressions merge if their tokens are
+    // the same
+    let source = "fn b();\nimpl S { fn c(); }\nconst X: usize = builtin # offset_of(S, x);\n";
+    let merged = merge_sources(&[
+        ("a.rs", source, Cfgs::new("foo")),
+        ("b.rs", source, Cfgs::new("bar")),
+    ])
+    .unwrap();
+    assert_eq!(merged, syn::parse_file(source).unwrap());
+
+    let err = crate::merge::<TokenStream>(&[
+        (quote!(a b), Cfgs::new("foo")),
+        (quote!(a), Cfgs::new("bar")),
+    ])
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Mismatch);
+}
+
+#[test]
+fn source_diagnostics() {
+    let err = merge_sources(&[
+        ("a.rs", "fn a() {}\n", Cfgs::new("foo")),
+        ("b.rs", "fn a() {}\n\nfn b(;\n", Cfgs::new("bar")),
+    ])
+    .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Parse);
+    assert_eq!(err.input, Some(1));
+    #[cfg(feature = "diagnostics")]
+    assert_eq!(
+        err.location.as_ref().map(|l| (l.line, l.column)),
+        Some((3, 5))
+    );
+
+    // `fn b();` is not valid at the top level, and is parsed as verbatim
+    // tokens, which can't be `cfg`-guarded
+    let err = merge_sources(&[
+        ("a.rs", "fn a() {}\n", Cfgs::new("foo")),
+        ("b.rs", "fn a() {}\nfn b();\n", Cfgs::new("bar")),
+    ])
+    .unwrap_err();
+    assert_eq!(err.input, Some(1));
+    #[cfg(feature = "diagnostics")]
+    assert_eq!(
+        err.to_string(),
+        "\
+error: can't add `#[cfg]` to verbatim tokens
+ --> b.rs:2:1
+  |
+2 | fn b();
+  | ^^^^^^^
+"
+        .trim_end(),
+    );
+
+    let merged = merge_sources(&[
+        ("a.rs", "fn a() {}\n", Cfgs::new("foo")),
+        ("b.rs", "fn b() {}\n", Cfgs::new("bar")),
+    ])
+    .unwrap();
+    assert_files_eq(
+        &merged,
+        quote! {
+            #[cfg(foo)]
+            fn a() {}
+            #[cfg(bar)]
+            fn b() {}
+        },
+    );
+}
+
+#[cfg(feature = "pretty")]
+#[test]
+fn preserve_formatting() {
+    let a = r#"//! Bindings.
+
+// Shared between platforms
+#[repr(C)]
+pub struct Shared { x: u8 }
+
+/// Only on foo.
+fn foo_only() {
+    call(1,2);
+}
+
+impl Shared {
+    // A comment on `a`
+    fn a(&self) {}
+
+    fn foo(&self) {}
+}
+"#;
+    let b = r#"// Shared between platforms
+#[repr(C)]
+pub struct Shared { x: u8 }
+
+impl Shared {
+    fn a(&self) {}
+    fn bar(&self) {}
+}
+
+fn bar_only() {}
+"#;
+    let merged =
+        merge_sources_preserving(&[("a.rs", a, Cfgs::new("foo")), ("b.rs", b, Cfgs::new("bar"))])
+            .unwrap();
+    assert_eq!(
+        merged,
+        r#"//! Bindings.
+
+// Shared between platforms
+#[repr(C)]
+pub struct Shared { x: u8 }
+
+/// Only on foo.
+#[cfg(foo)]
+fn foo_only() {
+    call(1,2);
+}
+
+impl Shared {
+    // A comment on `a`
+    fn a(&self) {}
+
+    #[cfg(foo)]
+    fn foo(&self) {}
+
+    #[cfg(bar)]
+    fn bar(&self) {}
+}
+
+#[cfg(bar)]
+fn bar_only() {}
+"#
+    );
+}
+
+#[cfg(feature = "pretty")]
+#[test]
+fn text_edits() {
+    let a = "// A comment\nfn shared() {}\n\nfn a() {}\n";
+    let b = "fn shared() {}\n\n/// B.\nfn b() {}\n";
+    let input = [("a.rs", a, Cfgs::new("foo")), ("b.rs", b, Cfgs::new("bar"))];
+
+    let edits = merge_sources_as_edits(&input, 0).unwrap();
+    assert_eq!(
+        edits,
+        [
+            TextEdit {
+                range: 29..29,
+                text: "#[cfg(foo)]\n".to_string(),
+            },
+            TextEdit {
+                range: 39..39,
+                text: "\n/// B.\n#[cfg(bar)]\nfn b() {}\n".to_string(),
+            },
+        ]
+    );
+    assert_eq!(
+        apply_edits(a, &edits),
+        merge_sources_preserving(&input).unwrap()
+    );
+
+    let edits = merge_sources_as_edits(&input, 1).unwrap();
+    assert_eq!(
+        apply_edits(b, &edits),
+        "fn shared() {}\n\n#[cfg(foo)]\nfn a() {}\n\n/// B.\n#[cfg(bar)]\nfn b() {}\n",
+    );
+
+    let err = merge_sources_as_edits(&input, 2).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::NoInput);
+    assert_eq!(err.to_string(), "error: there is no input 2");
+}
+
+#[cfg(feature = "diagnostics")]
+#[test]
+fn source_map() {
+    let a = "fn shared() {}\n\nimpl Foo {\n    fn a() {}\n}\n";
+    let b = "impl Foo {\n    fn b() {}\n}\n\nfn shared() {}\n";
+    let input = [
+        (syn::parse_file(a).unwrap(), Cfgs::new("foo")),
+        (syn::parse_file(b).unwrap(), Cfgs::new("bar")),
+    ];
+
+    let (file, map) = merge_files_with_source_map(&input, &MergeOptions::new()).unwrap();
+    assert_eq!(file, merge_files(&input).unwrap());
+    let origin = |input, lines| Origin {
+        input,
+        lines: Some(lines),
+    };
+    let summary: Vec<_> = map
+        .items
+        .iter()
+        .map(|item| {
+            (
+                item.path.clone(),
+                item.name.join(" > "),
+                item.origins.clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (vec![0], "fn shared".to_string(), vec![origin(0, 1..=1)]),
+            (
+                vec![1],
+                "impl Foo".to_string(),
+                vec![origin(0, 3..=5), origin(1, 1..=3)],
+            ),
+            (
+                vec![1, 0],
+                "impl Foo > fn a".to_string(),
+                vec![origin(0, 4..=4)]
+            ),
+            (
+                vec![1, 1],
+                "impl Foo > fn b".to_string(),
+                vec![origin(1, 2..=2)]
+            ),
+            (vec![2], "fn shared".to_string(), vec![origin(1, 5..=5)]),
+        ]
+    );
+}
+
+#[test]
+fn manifest() {
+    let previous = Manifest {
+        inputs: 2,
+        items: vec![ManifestItem {
+            path: vec![0],
+            name: vec!["fn len".to_string()],
+            public: true,
+            origins: vec![],
+            predicate: None,
+            decision: Decision::Shared,
+        }],
+    };
+    let input = parse_files(&[
+        (
+            quote! {
+                pub fn shared() {}
+                pub fn len() -> u32 { 0 }
+                fn only_a() {}
+            },
+            "foo",
+        ),
+        (
+            quote! {
+                pub fn shared() {}
+                pub fn len() -> u64 { 0 }
+            },
+            "bar",
+        ),
+    ]);
+    let (file, manifest) = merge_files_with_manifest(&input, &MergeOptions::new()).unwrap();
+    assert_eq!(file, merge_files(&input).unwrap());
+
+    let decisions: Vec<_> = manifest
+        .items
+        .iter()
+        .map(|item| {
+            (
+                item.name.join(" > "),
+                item.predicate.clone(),
+                item.decision.clone(),
+            )
+        })
+        .collect();
+    let duplicated = |this: &str, other: &str| Decision::Duplicated {
+        path: vec!["fn len".to_string()],
+        this: Some(format!("pub fn len () -> {this} {{ 0 }}")),
+        other: Some(format!("pub fn len () -> {other} {{ 0 }}")),
+    };
+    assert_eq!(
+        decisions,
+        [
+            ("fn shared".to_string(), None, Decision::Shared),
+            (
+                "fn len".to_string(),
+                Some("foo".to_string()),
+                duplicated("u32", "u64")
+            ),
+            (
+                "fn only_a".to_string(),
+                Some("foo".to_string()),
+                Decision::Specific
+            ),
+            (
+                "fn len".to_string(),
+                Some("bar".to_string()),
+                duplicated("u64", "u32")
+            ),
+        ]
+    );
+    assert_eq!((manifest.shared(), manifest.specific()), (1, 3));
+
+    let regressions = manifest.newly_specific(&previous);
+    assert_eq!(regressions.len(), 2);
+    assert!(regressions.iter().all(|item| item.name == ["fn len"]));
+
+    #[cfg(feature = "json")]
+    assert_eq!(
+        Manifest::from_json(&manifest.to_json().unwrap()).unwrap(),
+        manifest
+    );
+}
+
+#[test]
+fn manifest_decisions() {
+    let input = parse_files(&[
+        (
+            quote! {
+                unsafe impl X for Y {
+                    fn a() {}
+                    fn b() -> u32 { 0 }
+                }
+            },
+            "foo",
+        ),
+        (
+            quote! {
+                impl X for Y {
+                    fn a() {}
+                    fn b() -> u64 { 0 }
+                }
+            },
+            "bar",
+        ),
+    ]);
+    let options = MergeOptions::new().compile_error("unsupported target");
+    let (_, manifest) = merge_files_with_manifest(&input, &options).unwrap();
+
+    let decisions: Vec<_> = manifest
+        .items
+        .iter()
+        .filter(|item| item.path.len() == 1)
+        .map(|item| item.decision.clone())
+        .collect();
+    let duplicated = |this: &str, other: &str| Decision::Duplicated {
+        path: vec!["impl X for Y".to_string(), "fn b".to_string()],
+        this: Some(format!("fn b () -> {this} {{ 0 }}")),
+        other: Some(format!("fn b () -> {other} {{ 0 }}")),
+    };
+    assert_eq!(
+        decisions,
+        [
+            duplicated("u32", "u64"),
+            duplicated("u64", "u32"),
+            Decision::Generated,
+        ]
+    );
+    assert_eq!((manifest.shared(), manifest.specific()), (0, 2));
+}
+
+#[cfg(feature = "pretty")]
+#[test]
+fn report() {
+    let input = parse_files(&[
+        (
+            quote! {
+                fn shared() {}
+                fn len() -> u32 { 0 }
+            },
+            "foo",
+        ),
+        (
+            quote! {
+                fn shared() {}
+                fn len() -> u64 { 0 }
+                struct OnlyBar;
+            },
+            "bar",
+        ),
+    ]);
+    let html = html_report(&input, &MergeOptions::new()).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<th><code>foo</code></th><th><code>bar</code></th>"));
+    assert!(html.contains(
+        "<tr><td><code>struct OnlyBar</code></td>\
+         <td class=\"no\"></td><td class=\"yes\">&#10003;</td></tr>"
+    ));
+    assert_eq!(html.matches("<details>").count(), 1);
+    assert!(html.contains(
+        "<tr class=\"differs\"><td>fn len() -&gt; u32 {</td><td></td></tr>\n\
+         <tr class=\"differs\"><td></td><td>fn len() -&gt; u64 {</td></tr>\n\
+         <tr><td>    0</td><td>    0</td></tr>"
+    ));
+}
+
+#[test]
+fn print_cfg() {
+    let output = "debug_assertions\npanic=\"unwind\"\ntarget_os=\"linux\"\nunix\n";
+    let predicate = Predicate::KeyValue("target_os".into(), "linux".into());
+    let cfgs = Cfgs::from_print_cfg(predicate.clone(), output).unwrap();
+    assert_eq!(cfgs.predicate(), &predicate);
+    assert!(cfgs.holds(&Predicate::Name("unix".into())));
+    assert!(cfgs.holds(&Predicate::KeyValue("panic".into(), "unwind".into())));
+    assert!(!cfgs.holds(&Predicate::Name("windows".into())));
+
+    assert!(Cfgs::from_print_cfg(predicate, "all(unix)").is_err());
+}
+
+#[test]
+fn cfgs_with_facts() {
+    let linux = Predicate::KeyValue("target_os".into(), "linux".into());
+    let cfgs = Cfgs::with_facts(linux.clone(), [linux.clone()]).unwrap();
+    assert!(cfgs.holds(&linux));
+    let err = Cfgs::with_facts(linux.clone(), [linux.negate()]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "expected a cfg name or key-value pair, found `not(target_os = \"linux\")`"
+    );
+}
+
+#[test]
+fn cfgs_new() {
+    let foo = Predicate::Name("foo".into());
+    let cfgs = Cfgs::new("foo");
+    assert_eq!(cfgs.predicate(), &foo);
+    assert!(cfgs.holds(&foo));
+    assert_eq!(Cfgs::try_new("foo").unwrap(), cfgs);
+
+    let linux = Predicate::KeyValue("target_os".into(), "linux".into());
+    let cfgs = Cfgs::try_new("target_os = \"linux\"").unwrap();
+    assert_eq!(cfgs.predicate(), &linux);
+    assert!(cfgs.holds(&linux));
+    let cfgs = Cfgs::try_new("all(unix, target_os = \"linux\")").unwrap();
+    assert!(cfgs.holds(&Predicate::Name("unix".into())));
+    assert!(cfgs.holds(&linux));
+    assert!(Cfgs::try_new("not a cfg").is_err());
+    assert!(Cfgs::try_new("").is_err());
+}
+
+#[test]
+fn cfgs_facts() {
+    let file = syn::parse_quote! {
+        #[cfg(unix)]
+        fn unix() {}
+        #[cfg(all(unix, target_pointer_width = "64"))]
+        fn unix64() {}
+    };
+    let linux = Predicate::KeyValue("target_os".into(), "linux".into());
+
+    // Only the facts decide what holds, not the predicate naming the input
+    let cfgs = Cfgs::from_print_cfg(
+        linux.clone(),
+        "target_os=\"linux\"\ntarget_pointer_width=\"64\"\nunix\n",
+    )
+    .unwrap();
+    assert_files_eq(
+        &crate::specialize(&file, &cfgs),
+        quote! {
+            fn unix() {}
+            fn unix64() {}
+        },
+    );
+    let cfgs = Cfgs::try_new("all(unix, target_os = \"linux\")").unwrap();
+    assert_files_eq(
+        &crate::specialize(&file, &cfgs),
+        quote! {
+            fn unix() {}
+        },
+    );
+    let cfgs = Cfgs::from_predicate(linux);
+    assert_files_eq(&crate::specialize(&file, &cfgs), quote! {});
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn config() {
+    let dir = std::env::temp_dir().join(format!("syn-merge-config-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    for (target, source) in [
+        ("linux", "fn shared() {}\nmod tests { fn a() {} }\n"),
+        ("macos", "fn shared() {}\nmod tests { fn b() {} }\n"),
+    ] {
+        std::fs::create_dir_all(dir.join(target).join("sys")).unwrap();
+        std::fs::write(dir.join(target).join("sys/mod.rs"), source).unwrap();
+    }
+    std::fs::write(dir.join("linux/only.rs"), "fn linux() {}\n").unwrap();
+    std::fs::write(dir.join("linux.cfg"), "unix\ntarget_os=\"linux\"\n").unwrap();
+    std::fs::write(
+        dir.join("syn-merge.toml"),
+        r#"
+        output = "merged"
+
+        [[input]]
+        path = "linux"
+        cfg = 'target_os = "linux"'
+        print-cfg = "linux.cfg"
+
+        [[input]]
+        path = "macos"
+        cfg = 'target_os = "macos"'
+
+        [[override]]
+        module = "tests"
+        merge = false
+        "#,
+    )
+    .unwrap();
+
+    let config = Config::load(&dir.join("syn-merge.toml")).unwrap();
+    assert_eq!(config.style, Style::Pretty);
+    assert_eq!(config.inputs[1].path, dir.join("macos"));
+
+    let outputs = config.run().unwrap();
+    let outputs: Vec<_> = outputs
+        .iter()
+        .map(|output| (output.path.strip_prefix(&dir).unwrap(), &*output.contents))
+        .collect();
+    assert_eq!(
+        outputs,
+        [
+            (
+                Path::new("merged/only.rs"),
+                "#[cfg(target_os = \"linux\")]\nfn linux() {}\n",
+            ),
+            (
+                Path::new("merged/sys/mod.rs"),
+                "fn shared() {}\n\
+                 #[cfg(target_os = \"linux\")]\n\
+                 mod tests {\n    fn a() {}\n}\n\
+                 #[cfg(target_os = \"macos\")]\n\
+                 mod tests {\n    fn b() {}\n}\n",
+            ),
+        ]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Later overrides take precedence
+    let config = Config::from_toml(
+        r#"
+        input = []
+        output = "merged.rs"
+
+        [[override]]
+        module = "*"
+        merge = false
+
+        [[override]]
+        module = "ff*"
+        "#,
+    )
+    .unwrap();
+    let files = parse_files(files_with_cfg! {
+        #[cfg(foo)]
+        mod _ {
+            mod ffi {
+                fn a() {}
+            }
+            mod tests {
+                fn a() {}
+            }
+        }
+        #[cfg(bar)]
+        mod _ {
+            mod ffi {
+                fn b() {}
+            }
+            mod tests {
+                fn b() {}
+            }
+        }
+    });
+    assert_files_eq(
+        &merge_files_with_options(&files, &config.merge_options()).unwrap(),
+        quote! {
+            mod ffi {
+                #[cfg(foo)]
+                fn a() {}
+                #[cfg(bar)]
+                fn b() {}
+            }
+            #[cfg(foo)]
+            mod tests {
+                fn a() {}
+            }
+            #[cfg(bar)]
+            mod tests {
+                fn b() {}
+            }
+        },
+    );
+    assert!(Override {
+        module: "test*s".into(),
+        merge: false,
+    }
+    .matches("test_utils"));
+    assert!(!Override {
+        module: "tests".into(),
+        merge: false,
+    }
+    .matches("tests_utils"));
+    assert!(Override {
+        module: "tests".into(),
+        merge: false,
+    }
+    .matches("sys::tests"));
+
+    // Paths tell apart modules with the same name
+    let config = Config::from_toml(
+        r#"
+        input = []
+        output = "merged.rs"
+
+        [[override]]
+        module = "sys::tests"
+        merge = false
+        "#,
+    )
+    .unwrap();
+    let files = parse_files(files_with_cfg! {
+        #[cfg(foo)]
+        mod _ {
+            mod sys {
+                mod tests {
+                    fn a() {}
+                }
+            }
+            mod other {
+                mod tests {
+                    fn a() {}
+                }
+            }
+        }
+        #[cfg(bar)]
+        mod _ {
+            mod sys {
+                mod tests {
+                    fn b() {}
+                }
+            }
+            mod other {
+                mod tests {
+                    fn b() {}
+                }
+            }
+        }
+    });
+    assert_files_eq(
+        &merge_files_with_options(&files, &config.merge_options()).unwrap(),
+        quote! {
+            mod sys {
+                #[cfg(foo)]
+                mod tests {
+                    fn a() {}
+                }
+                #[cfg(bar)]
+                mod tests {
+                    fn b() {}
+                }
+            }
+            mod other {
+                mod tests {
+                    #[cfg(foo)]
+                    fn a() {}
+                    #[cfg(bar)]
+                    fn b() {}
+                }
+            }
+        },
+    );
+
+    assert!(matches!(
+        Config::from_toml("output = 1"),
+        Err(ConfigError::Parse(_))
+    ));
+}
+
+#[cfg(feature = "pretty")]
+#[test]
+fn build_script() {
+    let dir = std::env::temp_dir().join(format!("syn-merge-build-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.rs"), "fn shared() {}\nfn a() {}\n").unwrap();
+    std::fs::write(dir.join("b.rs"), "fn shared() {}\n").unwrap();
+
+    let build = build::Build::new()
+        .input(dir.join("a.rs"), Cfgs::new("foo"))
+        .input(dir.join("b.rs"), Cfgs::new("bar"))
+        .out_dir(dir.join("out"));
+    let path = build.write("merged.rs").unwrap();
+    assert_eq!(path, dir.join("out/merged.rs"));
+    let merged = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(merged, "fn shared() {}\n#[cfg(foo)]\nfn a() {}\n");
+    assert!(!build::write_if_changed(&path, &merged).unwrap());
+    assert!(build::write_if_changed(&path, "").unwrap());
+
+    std::fs::remove_file(dir.join("b.rs")).unwrap();
+    assert!(matches!(
+        build.write("merged.rs"),
+        Err(build::BuildError::Io(path, _)) if path == dir.join("b.rs")
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
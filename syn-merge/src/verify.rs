@@ -0,0 +1,346 @@
+//! Check that a merged file can be specialized back into its inputs.
+use std::fmt;
+
+use multidiff::DiffableSequence;
+use quote::ToTokens;
+use syn::visit_mut::{self, VisitMut};
+use syn::{Attribute, Field, File, FnArg, ForeignItem, ImplItem, Item, TraitItem, Variant};
+
+use crate::specialize::specialize_within;
+use crate::{Cfgs, Merge};
+
+/// What to ignore when comparing an input with the specialized merge result.
+#[derive(Debug, Clone)]
+pub struct Normalization {
+    specialize_inputs: bool,
+    ignore_attribute_order: bool,
+    ignore_docs: bool,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Normalization {
+    /// Specialize the inputs, but otherwise compare them as-is.
+    pub fn new() -> Self {
+        Self {
+            specialize_inputs: true,
+            ignore_attribute_order: false,
+            ignore_docs: false,
+        }
+    }
+
+    /// Whether to also specialize the inputs for their configuration.
+    ///
+    /// This is needed if the inputs contain `cfg`s of their own that are
+    /// decided by the facts of the configuration. Enabled by default.
+    ///
+    /// Names and key-value pairs that no configuration mentions are never
+    /// evaluated, so items with e.g. `#[cfg(feature = "x")]` are compared
+    /// along with the undecided part of their `cfg`.
+    pub fn specialize_inputs(mut self, value: bool) -> Self {
+        self.specialize_inputs = value;
+        self
+    }
+
+    /// Whether to ignore the order of attributes on a node.
+    pub fn ignore_attribute_order(mut self, value: bool) -> Self {
+        self.ignore_attribute_order = value;
+        self
+    }
+
+    /// Whether to ignore doc comments and `#[doc]` attributes.
+    pub fn ignore_docs(mut self, value: bool) -> Self {
+        self.ignore_docs = value;
+        self
+    }
+
+    fn apply(&self, file: &File, cfgs: &Cfgs, configs: &[Cfgs]) -> File {
+        let mut file = if self.specialize_inputs {
+            specialize_within(file, cfgs, configs)
+        } else {
+            file.clone()
+        };
+        if self.ignore_attribute_order || self.ignore_docs {
+            Normalizer(self).visit_file_mut(&mut file);
+        }
+        file
+    }
+
+    fn normalize_attrs(&self, attrs: &mut Vec<Attribute>) {
+        if self.ignore_docs {
+            attrs.retain(|attr| !attr.path().is_ident("doc"));
+        }
+        if self.ignore_attribute_order {
+            attrs.sort_by_cached_key(|attr| attr.to_token_stream().to_string());
+        }
+    }
+}
+
+struct Normalizer<'a>(&'a Normalization);
+
+impl Normalizer<'_> {
+    fn normalize<T: Merge>(&self, node: &mut T) {
+        if let Some(attrs) = node.attrs_mut() {
+            self.0.normalize_attrs(attrs);
+        }
+    }
+}
+
+impl VisitMut for Normalizer<'_> {
+    fn visit_file_mut(&mut self, node: &mut File) {
+        self.0.normalize_attrs(&mut node.attrs);
+        visit_mut::visit_file_mut(self, node);
+    }
+
+    fn visit_item_mut(&mut self, node: &mut Item) {
+        self.normalize(node);
+        visit_mut::visit_item_mut(self, node);
+    }
+
+    fn visit_impl_item_mut(&mut self, node: &mut ImplItem) {
+        self.normalize(node);
+        visit_mut::visit_impl_item_mut(self, node);
+    }
+
+    fn visit_trait_item_mut(&mut self, node: &mut TraitItem) {
+        self.normalize(node);
+        visit_mut::visit_trait_item_mut(self, node);
+    }
+
+    fn visit_foreign_item_mut(&mut self, node: &mut ForeignItem) {
+        self.normalize(node);
+        visit_mut::visit_foreign_item_mut(self, node);
+    }
+
+    fn visit_field_mut(&mut self, node: &mut Field) {
+        self.normalize(node);
+        visit_mut::visit_field_mut(self, node);
+    }
+
+    fn visit_variant_mut(&mut self, node: &mut Variant) {
+        self.normalize(node);
+        visit_mut::visit_variant_mut(self, node);
+    }
+
+    fn visit_fn_arg_mut(&mut self, node: &mut FnArg) {
+        self.normalize(node);
+        visit_mut::visit_fn_arg_mut(self, node);
+    }
+}
+
+/// The first difference found by [`verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    /// The index of the input that couldn't be reconstructed.
+    pub input: usize,
+    /// The path to the diverging item, e.g. `["mod sys", "fn foo"]`.
+    pub path: Vec<String>,
+    /// The item in the input, if any.
+    pub expected: Option<String>,
+    /// The item in the specialized merge result, if any.
+    pub actual: Option<String>,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "input {} differs", self.input)?;
+        if !self.path.is_empty() {
+            write!(f, " at `{}`", self.path.join(" > "))?;
+        }
+        match (&self.expected, &self.actual) {
+            (Some(expected), Some(actual)) => {
+                write!(f, ": expected `{expected}`, found `{actual}`")
+            }
+            (Some(expected), None) => write!(f, ": missing `{expected}`"),
+            (None, Some(actual)) => write!(f, ": unexpected `{actual}`"),
+            (None, None) => write!(f, ": file attributes differ"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Verify that `merged` specializes back into each of the inputs.
+///
+/// The merged file is [`specialize`][crate::specialize]d for each input's [`Cfgs`], and
+/// compared structurally with that input after applying `normalization` to
+/// both. Only the names and key-value pairs that the configurations mention
+/// are evaluated, the rest of each `cfg` is kept and compared. On failure,
+/// the first diverging item is reported.
+pub fn verify(
+    input: &[(File, Cfgs)],
+    merged: &File,
+    normalization: &Normalization,
+) -> Result<(), VerifyError> {
+    let configs: Vec<Cfgs> = input.iter().map(|(_, cfgs)| cfgs.clone()).collect();
+    for (idx, (file, cfgs)) in input.iter().enumerate() {
+        let expected = normalization.apply(file, cfgs, &configs);
+        let actual =
+            normalization.apply(&specialize_within(merged, cfgs, &configs), cfgs, &configs);
+        if expected == actual {
+            continue;
+        }
+        let mut path = Vec::new();
+        let (expected, actual) =
+            first_difference(&expected.items, &actual.items, &mut path).unwrap_or_default();
+        return Err(VerifyError {
+            input: idx,
+            path,
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+pub(crate) type Difference = (Option<String>, Option<String>);
+
+/// A list of nodes, lined up with another list by their descriptions.
+struct Described<'a, T>(&'a [T]);
+
+impl<'a, T: Describe> DiffableSequence for Described<'a, T> {
+    type Item = &'a T;
+
+    fn eq(a: &Self::Item, b: &Self::Item) -> bool {
+        a.describe() == b.describe()
+    }
+
+    fn get_iter(&self) -> impl Iterator<Item = Self::Item> {
+        self.0.iter()
+    }
+}
+
+/// The first difference between the nodes, after lining them up, with the
+/// descriptions of the nodes leading to it pushed to `path`.
+pub(crate) fn first_difference<T: Describe>(
+    expected: &[T],
+    actual: &[T],
+    path: &mut Vec<String>,
+) -> Option<Difference> {
+    // Line up the nodes like merging does, such that a missing node is
+    // reported as such instead of as a difference between the nodes after it
+    for indexes in multidiff::multidiff_indexes(&[Described(expected), Described(actual)]) {
+        match (
+            indexes[0].map(|i| &expected[i]),
+            indexes[1].map(|i| &actual[i]),
+        ) {
+            (Some(expected), Some(actual)) if expected == actual => {}
+            (Some(expected), Some(actual)) => {
+                path.push(expected.describe());
+                if let Some(difference) = expected.first_inner_difference(actual, path) {
+                    return Some(difference);
+                }
+                return Some((Some(expected.to_string()), Some(actual.to_string())));
+            }
+            (expected, actual) => {
+                path.push(expected.or(actual).unwrap().describe());
+                return Some((
+                    expected.map(Describe::to_string),
+                    actual.map(Describe::to_string),
+                ));
+            }
+        }
+    }
+    None
+}
+
+pub(crate) trait Describe: PartialEq + ToTokens {
+    /// A short description, like `fn foo` or `impl Foo`.
+    fn describe(&self) -> String;
+
+    /// The first difference between the children of two items that have
+    /// the same description.
+    fn first_inner_difference(&self, other: &Self, path: &mut Vec<String>) -> Option<Difference> {
+        let _ = (other, path);
+        None
+    }
+
+    fn to_string(&self) -> String {
+        self.to_token_stream().to_string()
+    }
+}
+
+impl Describe for Item {
+    fn describe(&self) -> String {
+        match self {
+            Item::Const(item) => format!("const {}", item.ident),
+            Item::Enum(item) => format!("enum {}", item.ident),
+            Item::ExternCrate(item) => format!("extern crate {}", item.ident),
+            Item::Fn(item) => format!("fn {}", item.sig.ident),
+            Item::ForeignMod(item) => format!("extern {}", item.abi.to_token_stream()),
+            Item::Impl(item) => match &item.trait_ {
+                Some((_, path, _)) => format!(
+                    "impl {} for {}",
+                    path.to_token_stream(),
+                    item.self_ty.to_token_stream()
+                ),
+                None => format!("impl {}", item.self_ty.to_token_stream()),
+            },
+            Item::Macro(item) => format!("{}!", item.mac.path.to_token_stream()),
+            Item::Mod(item) => format!("mod {}", item.ident),
+            Item::Static(item) => format!("static {}", item.ident),
+            Item::Struct(item) => format!("struct {}", item.ident),
+            Item::Trait(item) => format!("trait {}", item.ident),
+            Item::TraitAlias(item) => format!("trait {}", item.ident),
+            Item::Type(item) => format!("type {}", item.ident),
+            Item::Union(item) => format!("union {}", item.ident),
+            Item::Use(item) => format!("use {}", item.tree.to_token_stream()),
+            _ => self.to_token_stream().to_string(),
+        }
+    }
+
+    fn first_inner_difference(&self, other: &Self, path: &mut Vec<String>) -> Option<Difference> {
+        match (self, other) {
+            (Item::Mod(this), Item::Mod(other)) if this.attrs == other.attrs => {
+                let (Some((_, this)), Some((_, other))) = (&this.content, &other.content) else {
+                    return None;
+                };
+                first_difference(this, other, path)
+            }
+            (Item::Impl(this), Item::Impl(other)) if this.attrs == other.attrs => {
+                first_difference(&this.items, &other.items, path)
+            }
+            (Item::Trait(this), Item::Trait(other)) if this.attrs == other.attrs => {
+                first_difference(&this.items, &other.items, path)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Describe for ImplItem {
+    fn describe(&self) -> String {
+        match self {
+            ImplItem::Const(item) => format!("const {}", item.ident),
+            ImplItem::Fn(item) => format!("fn {}", item.sig.ident),
+            ImplItem::Type(item) => format!("type {}", item.ident),
+            _ => self.to_token_stream().to_string(),
+        }
+    }
+}
+
+impl Describe for TraitItem {
+    fn describe(&self) -> String {
+        match self {
+            TraitItem::Const(item) => format!("const {}", item.ident),
+            TraitItem::Fn(item) => format!("fn {}", item.sig.ident),
+            TraitItem::Type(item) => format!("type {}", item.ident),
+            _ => self.to_token_stream().to_string(),
+        }
+    }
+}
+
+impl Describe for ForeignItem {
+    fn describe(&self) -> String {
+        match self {
+            ForeignItem::Fn(item) => format!("fn {}", item.sig.ident),
+            ForeignItem::Static(item) => format!("static {}", item.ident),
+            ForeignItem::Type(item) => format!("type {}", item.ident),
+            _ => self.to_token_stream().to_string(),
+        }
+    }
+}